@@ -0,0 +1,91 @@
+use {
+    std::{
+        collections::HashMap,
+        path::{
+            Path,
+            PathBuf,
+        },
+    },
+    bytesize::ByteSize,
+    serde::Deserialize,
+    wheel::fs,
+    crate::{
+        BACKUP_PATH,
+        DEFAULT_TIMESTAMP_FORMAT,
+        Error,
+    },
+};
+
+const CONFIG_PATH: &str = "/etc/wurstminebackup.toml";
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub backup_path: PathBuf,
+    pub default_world: String,
+    pub save_settle_secs: u64,
+    /// A `chrono` format string for the timestamps in backup filenames. Changing this makes existing backups unrecognizable.
+    pub timestamp_format: String,
+    /// Only used if the webhook isn't given on the command line, in the `WURSTMINEBACKUP_DISCORD_WEBHOOK` environment variable, or as a `discord-webhook` systemd credential,
+    /// which avoid storing the secret in a world-readable file.
+    pub discord_webhook: Option<String>,
+    /// The sender address of email reports sent using `--email`. Defaults to one chosen by `sendmail`.
+    pub email_from: Option<String>,
+    /// A program compatible with `sendmail -t` used to send email reports, which is responsible for delivering them, e.g. by relaying them to an SMTP server.
+    pub sendmail: PathBuf,
+    /// Rsync filter patterns for files in the world directory which shouldn't be backed up.
+    pub exclude: Vec<String>,
+    /// Per-world settings, keyed by world name.
+    pub worlds: HashMap<String, WorldConfig>,
+    /// Whether to record each backup created, deleted, or compressed in the audit log.
+    pub audit_log: bool,
+    /// Defaults to `wurstminebackup.log` in the backup path. Required for an audit log when backing up to a remote host.
+    pub audit_log_path: Option<PathBuf>,
+    /// Once the audit log reaches this size, it's rotated to `<audit_log_path>.1`.
+    pub audit_log_max_size: ByteSize,
+}
+
+impl Config {
+    /// Loads the config from the given path, or from `/etc/wurstminebackup.toml` if none is given.
+    ///
+    /// If no path is given and the default config file doesn't exist, the default config is returned.
+    pub async fn load(path: Option<&Path>) -> Result<Self, Error> {
+        let path = match path {
+            Some(path) => path,
+            None => {
+                let path = Path::new(CONFIG_PATH);
+                if !fs::exists(path).await? { return Ok(Self::default()) }
+                path
+            }
+        };
+        Ok(toml::from_str(&fs::read_to_string(path).await?)?)
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            backup_path: PathBuf::from(BACKUP_PATH),
+            default_world: String::from("wurstmineberg"),
+            save_settle_secs: 10,
+            timestamp_format: DEFAULT_TIMESTAMP_FORMAT.to_owned(),
+            discord_webhook: None,
+            email_from: None,
+            sendmail: PathBuf::from("/usr/sbin/sendmail"),
+            exclude: Vec::default(),
+            worlds: HashMap::default(),
+            audit_log: true,
+            audit_log_path: None,
+            audit_log_max_size: ByteSize::mib(10),
+        }
+    }
+}
+
+/// Retention limits for a world, enforced after each backup in addition to deleting backups when the disk is full.
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct WorldConfig {
+    pub max_backups: Option<usize>,
+    /// For example `"200 GiB"`.
+    pub max_total_size: Option<ByteSize>,
+}
@@ -0,0 +1,2913 @@
+//! The backup logic behind the `wurstminebackup` command, for use by other tools that manage the same backups.
+
+// `unused_crate_dependencies` is checked by the binary instead, since some dependencies are only used there
+#![deny(rust_2018_idioms, unused, unused_import_braces, unused_lifetimes, unused_qualifications, warnings)]
+#![forbid(unsafe_code)]
+
+use {
+    std::{
+        cell::RefCell,
+        collections::{
+            BTreeMap,
+            HashMap,
+            HashSet,
+        },
+        env,
+        ffi::{
+            OsStr,
+            OsString,
+        },
+        fmt,
+        io::{
+            self,
+            Read as _,
+            Write as _,
+        },
+        iter,
+        ops::{
+            Bound,
+            RangeBounds as _,
+            RangeInclusive,
+        },
+        path::{
+            Path,
+            PathBuf,
+        },
+        pin::{
+            Pin,
+            pin,
+        },
+        process::{
+            ExitStatus,
+            Output,
+            Stdio,
+        },
+        sync::Arc,
+        time::{
+            Duration,
+            Instant,
+            SystemTime,
+        },
+    },
+    bytesize::ByteSize,
+    chrono::prelude::*,
+    fs2::FileExt as _,
+    futures::{
+        future::{
+            Future,
+            FutureExt as _,
+            LocalBoxFuture,
+            Shared,
+        },
+        stream::{
+            self,
+            StreamExt as _,
+            TryStreamExt as _,
+        },
+    },
+    globset::{
+        GlobBuilder,
+        GlobSet,
+        GlobSetBuilder,
+    },
+    itertools::{
+        EitherOrBoth,
+        Itertools as _,
+    },
+    lazy_regex::{
+        Regex,
+        regex,
+        regex_captures,
+    },
+    serde::{
+        Deserialize,
+        Serialize,
+    },
+    serde_json::json,
+    sha2::{
+        Digest as _,
+        Sha256,
+    },
+    systemstat::{
+        Filesystem,
+        Platform as _,
+        System,
+    },
+    tokio::{
+        io::{
+            AsyncBufReadExt as _,
+            AsyncReadExt as _,
+            AsyncWriteExt as _,
+            BufReader,
+        },
+        process::Command,
+        signal::unix::{
+            SignalKind,
+            signal,
+        },
+        time::{
+            sleep,
+            timeout,
+        },
+    },
+    tracing::{
+        Level,
+        debug,
+        error,
+        info,
+        trace,
+        warn,
+    },
+    wheel::{
+        fs,
+        traits::{
+            AsyncCommandOutputExt as _,
+            IoResultExt as _,
+        },
+    },
+    crate::{
+        config::WorldConfig,
+        metadata::Metadata,
+        snapshot::Snapshot,
+    },
+};
+
+pub mod config;
+mod metadata;
+mod server;
+mod snapshot;
+#[cfg(test)] mod tests;
+
+pub use crate::{
+    server::Server,
+    snapshot::SnapshotKind,
+};
+
+const BACKUP_PATH: &str = "/media/backup/world";
+/// The audit log's filename in the backup path, unless `audit_log_path` is set in the config.
+pub const AUDIT_LOG_FILENAME: &str = "wurstminebackup.log";
+/// Backup timestamps are always generated from `Utc::now`, so they're in UTC regardless of the system timezone.
+const DEFAULT_TIMESTAMP_FORMAT: &str = "%Y-%m-%d_%H-%M-%S";
+
+/// The format of the timestamps in backup filenames, set from the `timestamp_format` config option.
+pub struct TimestampFormat {
+    format: String,
+    /// Matches a backup filename, capturing the timestamp, the Minecraft version, and the extension of compressed backups.
+    filename_regex: Regex,
+}
+
+impl TimestampFormat {
+    /// Validates the given `chrono` format string.
+    ///
+    /// To keep filenames safe and parsing lossless, the format may only contain ASCII letters, digits, `-`, `_`, and `.`,
+    /// and each of the `%Y`, `%m`, `%d`, `%H`, `%M`, and `%S` specifiers exactly once.
+    pub fn new(format: &str) -> Result<Self, Error> {
+        let mut regex = String::from("^(");
+        let mut specifiers = Vec::default();
+        let mut chars = format.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '%' => {
+                    let specifier = chars.next().ok_or(Error::TimestampFormat("format ends with %"))?;
+                    regex.push_str(match specifier {
+                        'Y' => "[0-9]{4}",
+                        'm' | 'd' | 'H' | 'M' | 'S' => "[0-9]{2}",
+                        _ => return Err(Error::TimestampFormat("only the %Y, %m, %d, %H, %M, and %S specifiers are supported")),
+                    });
+                    if specifiers.contains(&specifier) { return Err(Error::TimestampFormat("each specifier may only be used once")) }
+                    specifiers.push(specifier);
+                }
+                'A'..='Z' | 'a'..='z' | '0'..='9' | '-' | '_' | '.' => regex.push_str(&regex::escape(&c.to_string())),
+                _ => return Err(Error::TimestampFormat("only ASCII letters, digits, -, _, and . are allowed")),
+            }
+        }
+        if specifiers.len() < 6 { return Err(Error::TimestampFormat("all of %Y, %m, %d, %H, %M, and %S are required")) }
+        regex.push_str(r")_(.+?)(\.tar\.gz|\.tar\.zst)?$");
+        Ok(Self { format: format.to_owned(), filename_regex: Regex::new(&regex).expect("generated regex is valid") })
+    }
+
+    pub fn display(&self, timestamp: DateTime<Utc>) -> impl fmt::Display + '_ {
+        timestamp.format(&self.format)
+    }
+
+    pub fn parse(&self, timestamp: &str) -> Result<DateTime<Utc>, chrono::format::ParseError> {
+        Ok(NaiveDateTime::parse_from_str(timestamp, &self.format)?.and_utc())
+    }
+
+    /// Parses a backup filename into its timestamp, its Minecraft version, and how it's compressed.
+    fn parse_filename<'a>(&self, filename: &'a str) -> Result<(DateTime<Utc>, &'a str, Compression), Error> {
+        let captures = self.filename_regex.captures(filename).ok_or(Error::FilenameFormat)?;
+        let timestamp = captures.get(1).expect("always captured").as_str();
+        let version = captures.get(2).expect("always captured").as_str();
+        let compression = match captures.get(3).map(|extension| extension.as_str()) {
+            Some(".tar.gz") => Compression::Gzip,
+            Some(".tar.zst") => Compression::Zstd,
+            _ => Compression::None,
+        };
+        Ok((self.parse(timestamp)?, version, compression))
+    }
+}
+
+impl Default for TimestampFormat {
+    fn default() -> Self {
+        Self::new(DEFAULT_TIMESTAMP_FORMAT).expect("default timestamp format is valid")
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)] ChronoParse(#[from] chrono::format::ParseError),
+    #[error(transparent)] Config(#[from] toml::de::Error),
+    #[error(transparent)] Glob(#[from] globset::Error),
+    #[error(transparent)] Io(#[from] io::Error),
+    #[error(transparent)] Json(#[from] serde_json::Error),
+    #[error(transparent)] Minecraft(#[from] systemd_minecraft::Error),
+    #[error(transparent)] Wheel(#[from] wheel::Error),
+    #[error("another wurstminebackup process{} is already using the backups of the {world} world", pid.map(|pid| format!(" (PID {pid})")).unwrap_or_default())]
+    AlreadyRunning {
+        world: String,
+        pid: Option<u32>,
+    },
+    #[error("{prefix} matches multiple backups: {}", candidates.join(", "))]
+    AmbiguousPrefix {
+        prefix: String,
+        candidates: Vec<String>,
+    },
+    #[error("multiple backups with timestamp {0}")]
+    AmbiguousTimestamp(String),
+    #[error("checksum of {} does not match its .sha256 file", .0.display())]
+    ChecksumMismatch(PathBuf),
+    #[error("compression level {level} is not supported for {}, expected {}", compression.description(), compression.levels().map_or_else(|| String::from("no level"), |levels| format!("{} to {}", levels.start(), levels.end())))]
+    CompressionLevel {
+        level: u8,
+        compression: Compression,
+    },
+    #[error("{0} backup(s) failed verification")]
+    Corrupt(usize),
+    #[error("{0} isn't a dimension name like the_nether or mymod:voidworld")]
+    DimensionName(String),
+    #[error("not enough room on the backup drive: {required} needed, but only {available} available after deleting as many old backups as possible")]
+    DiskSpace {
+        required: ByteSize,
+        available: ByteSize,
+    },
+    #[error("writing the backup would fill {projected:.1}% of the backup drive (currently {current:.1}%), more than the maximum of {max}% set by --max-disk-usage-percent")]
+    DiskUsage {
+        current: f64,
+        projected: f64,
+        max: f64,
+    },
+    #[error("the backup is of Minecraft {backup}, which is older than the server's version {live}, use --allow-downgrade to restore it anyway")]
+    Downgrade {
+        backup: String,
+        live: String,
+    },
+    #[error("a backup with timestamp {0} already exists")]
+    DuplicateTimestamp(String),
+    #[error("the backup is compressed using {}, so it can only be exported as a .{} archive", compression.description(), compression.extension().unwrap_or("tar"))]
+    ExportCompression {
+        compression: Compression,
+    },
+    #[error("{} isn't a .tar, .tar.gz, or .tar.zst archive", .0.display())]
+    ExportFormat(PathBuf),
+    #[error("found file in backup path not matching the filename format")]
+    FilenameFormat,
+    #[error("the flat layout only supports one world per backup path")]
+    FlatLayout,
+    #[error("{} isn't a .tar.gz or .tar.zst archive", .0.display())]
+    ImportFormat(PathBuf),
+    #[error("backup {} is missing the level.dat or region files, so the world may have been missing or incomplete", .0.display())]
+    IncompleteWorld(PathBuf),
+    #[error("interrupted by a signal")]
+    Interrupted,
+    #[error("{0} is neither a timestamp in the configured format nor a date like 2024-01-31")]
+    InvalidDate(String),
+    #[error("--since is after --until")]
+    InvertedRange,
+    #[error("couldn't determine the Minecraft version from server jar {}, expected a filename like minecraft_server.1.20.4.jar or a version.json in the world directory or the jar", .0.display())]
+    JarPath(PathBuf),
+    #[error("refusing to prune all backups, --keep must be at least 1 unless --since or --until is given")]
+    KeepZero,
+    #[error("backup path {} does not exist", .0.display())]
+    NoBackupPath(PathBuf),
+    #[error("failed to check file system stats at backup directory")]
+    NoMount,
+    #[error("no backup with a timestamp starting with {0}")]
+    NoMatchingBackup(String),
+    #[error("no backup with timestamp {0}")]
+    NoSuchBackup(String),
+    #[error("there is no {0} world, check the spelling of the world name")]
+    NoSuchWorld(String),
+    #[error("{} does not contain a level.dat, so it doesn't look like a Minecraft world", .0.display())]
+    NotAWorld(PathBuf),
+    #[error("the {world} world has fewer than {nth} backup(s)")]
+    NotEnoughBackups {
+        world: String,
+        nth: usize,
+    },
+    #[error("non-UTF-8 filename")]
+    OsString(OsString),
+    #[error("{} already exists, use --force to overwrite it", .0.display())]
+    Overwrite(PathBuf),
+    #[error("the overworld is the world directory itself, so it can't be skipped")]
+    Overworld,
+    #[error("{0} isn't supported with a remote backup path")]
+    Remote(&'static str),
+    #[error("the backup path is on a remote host, so free space can't be checked and old backups can't be deleted or compressed; pass --remote-unchecked to back up anyway")]
+    RemoteUnchecked,
+    #[error("sendmail exited with {0}")]
+    Sendmail(ExitStatus),
+    #[error("failed to send `{command}` to the {world} world's server: {inner} (check that the server is running and its console accepts commands, or use --no-save-toggle to back up without disabling saves)")]
+    ServerCommand {
+        world: String,
+        command: &'static str,
+        #[source] inner: systemd_minecraft::Error,
+    },
+    #[error("the {0} world's server isn't running, start it or use --no-save-toggle to back up the stopped world")]
+    ServerStopped(String),
+    #[error("{} isn't on a {} file system, so it can't be snapshotted", dir.display(), kind.fs_type())]
+    SnapshotUnsupported {
+        kind: SnapshotKind,
+        dir: PathBuf,
+    },
+    #[error("tar exited with {0}")]
+    Tar(ExitStatus),
+    #[error("{0} timed out")]
+    Timeout(&'static str),
+    #[error("invalid timestamp_format in config: {0}")]
+    TimestampFormat(&'static str),
+    #[error("not confirmed, nothing was changed (use --yes to skip the confirmation, e.g. when running without a terminal)")]
+    Unconfirmed,
+    #[error("backup path {} is on the root file system, the backup drive may not be mounted (use --allow-unmounted to back up anyway)", .0.display())]
+    Unmounted(PathBuf),
+    #[error("non-UTF-8 filename")]
+    Utf8,
+    #[error("failed to back up {0} world(s)")]
+    Worlds(usize),
+}
+
+impl Error {
+    /// The exit status for errors which scripts may want to handle specifically. See the readme for the meaning of each code.
+    ///
+    /// Other errors exit with status 1.
+    pub fn exit_code(&self) -> Option<i32> {
+        match self {
+            Self::DiskSpace { .. } | Self::DiskUsage { .. } => Some(10),
+            Self::NoBackupPath(_) | Self::NoMount | Self::Unmounted(_) => Some(11),
+            Self::Minecraft(_) | Self::ServerCommand { .. } | Self::ServerStopped(_) => Some(12),
+            Self::ChecksumMismatch(_) | Self::Corrupt(_) | Self::FilenameFormat | Self::IncompleteWorld(_) => Some(13),
+            Self::AlreadyRunning { .. } => Some(14),
+            Self::Worlds(_) => Some(15),
+            Self::AmbiguousPrefix { .. } | Self::CompressionLevel { .. } | Self::DimensionName(_) | Self::FlatLayout | Self::InvalidDate(_) | Self::InvertedRange | Self::NoSuchWorld(_) | Self::Overworld => Some(2),
+            Self::Interrupted => Some(130),
+            _ => None,
+        }
+    }
+}
+
+impl From<OsString> for Error {
+    fn from(value: OsString) -> Self {
+        Self::OsString(value)
+    }
+}
+
+/// The maximum number of directory entries inspected concurrently within each directory by recursive functions like [`dir_size`].
+const DIR_SIZE_CONCURRENCY: usize = 16;
+
+/// Rsync-style `--exclude` patterns.
+#[derive(Default)]
+pub struct Excludes {
+    patterns: Vec<String>,
+    /// Matches excluded paths relative to the `rsync` transfer root, i.e. the parent of the world directory.
+    any: GlobSet,
+    /// Matches paths which are only excluded if they're directories, i.e. patterns with a trailing slash.
+    dirs: GlobSet,
+}
+
+impl Excludes {
+    pub fn new(patterns: Vec<String>) -> Result<Self, globset::Error> {
+        let mut any = GlobSetBuilder::new();
+        let mut dirs = GlobSetBuilder::new();
+        for pattern in &patterns {
+            let (pattern, dir_only) = if let Some(pattern) = pattern.strip_suffix('/') { (pattern, true) } else { (&**pattern, false) };
+            // like rsync, patterns with a leading slash are anchored to the transfer root and others may match at any depth
+            let glob = GlobBuilder::new(&if let Some(pattern) = pattern.strip_prefix('/') { pattern.to_owned() } else { format!("**/{pattern}") })
+                .literal_separator(true)
+                .build()?;
+            if dir_only { dirs.add(glob) } else { any.add(glob) };
+        }
+        Ok(Self { any: any.build()?, dirs: dirs.build()?, patterns })
+    }
+
+    fn is_excluded(&self, relative_path: &Path, is_dir: bool) -> bool {
+        self.any.is_match(relative_path) || is_dir && self.dirs.is_match(relative_path)
+    }
+}
+
+/// The directory of the dimension with the given name, like `the_nether` or `mymod:voidworld`, relative to the world directory.
+pub fn dimension_dir(name: &str) -> Result<PathBuf, Error> {
+    let (namespace, path) = name.split_once(':').unwrap_or(("minecraft", name));
+    // only the characters allowed in resource locations, which also keeps the name from being interpreted as a pattern
+    let is_valid = |part: &str, allowed: &[char]| !part.is_empty() && part.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || allowed.contains(&c));
+    if !is_valid(namespace, &['_', '-', '.']) || !is_valid(path, &['_', '-', '.', '/']) || path.split('/').any(|component| component.is_empty() || component == "." || component == "..") { return Err(Error::DimensionName(name.to_owned())) }
+    Ok(match (namespace, path) {
+        ("minecraft", "overworld") => return Err(Error::Overworld),
+        ("minecraft", "the_nether") => PathBuf::from("DIM-1"),
+        ("minecraft", "the_end") => PathBuf::from("DIM1"),
+        (namespace, path) => Path::new("dimensions").join(namespace).join(path),
+    })
+}
+
+/// The `--exclude` pattern skipping the dimension directory returned by [`dimension_dir`] in any world.
+pub fn dimension_exclude(dir: &Path) -> String {
+    format!("/*/{}/", dir.display())
+}
+
+/// Whether the given error means that a file or directory doesn't exist, e.g. because the running server deleted it while it was being inspected.
+fn is_not_found(e: &wheel::Error) -> bool {
+    matches!(e, wheel::Error::Io { inner, .. } if inner.kind() == io::ErrorKind::NotFound)
+}
+
+/// The total size of the given path, not following symlinks. Files and directories inside it which are deleted while it's being inspected count as zero bytes.
+fn dir_size(path: impl AsRef<Path>) -> Pin<Box<dyn Future<Output = wheel::Result<ByteSize>>>> {
+    dir_size_excluding(path, Arc::default(), PathBuf::default())
+}
+
+//FROM https://docs.rs/fs_extra/1.3.0/src/fs_extra/dir.rs.html#786-816 modified to be async and concurrent, use ByteSize, and support excludes
+/// Like [`dir_size`], but skips paths matching the given excludes. `relative_path` is the path of `path` relative to the `rsync` transfer root.
+fn dir_size_excluding(path: impl AsRef<Path>, excludes: Arc<Excludes>, relative_path: PathBuf) -> Pin<Box<dyn Future<Output = wheel::Result<ByteSize>>>> {
+    let path = path.as_ref().to_owned();
+    Box::pin(async move {
+        // Using `fs::symlink_metadata` since we don't want to follow symlinks,
+        // as we're calculating the exact size of the requested path itself.
+        let path_metadata = fs::symlink_metadata(&path).await?;
+
+        if path_metadata.is_dir() {
+            fs::read_dir(path)
+                .map_ok(|entry| {
+                    let excludes = excludes.clone();
+                    let relative_path = relative_path.join(entry.file_name());
+                    async move {
+                        // `DirEntry::metadata` does not follow symlinks (unlike `fs::metadata`), so in the
+                        // case of symlinks, this is the size of the symlink itself, not its target.
+                        let entry_metadata = match entry.metadata().await {
+                            Ok(entry_metadata) => entry_metadata,
+                            // deleted since the directory was read, e.g. a temporary file of the running server
+                            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(ByteSize::default()),
+                            Err(e) => return Err(e).at(entry.path()), //TODO wheel
+                        };
+
+                        if excludes.is_excluded(&relative_path, entry_metadata.is_dir()) {
+                            Ok(ByteSize::default())
+                        } else if entry_metadata.is_dir() {
+                            // The size of the directory entry itself will be counted inside the `get_size()` call,
+                            // so we intentionally don't also add `entry_metadata.len()` to the total here.
+                            match dir_size_excluding(entry.path(), excludes, relative_path).await {
+                                Err(e) if is_not_found(&e) => Ok(ByteSize::default()),
+                                res => res,
+                            }
+                        } else {
+                            Ok(ByteSize::b(entry_metadata.len()))
+                        }
+                    }
+                })
+                .try_buffer_unordered(DIR_SIZE_CONCURRENCY)
+                .try_fold(ByteSize::default(), |size_in_bytes, entry_size| async move { Ok(size_in_bytes + entry_size) })
+                .await
+        } else {
+            Ok(ByteSize::b(path_metadata.len()))
+        }
+    })
+}
+
+/// The most recent modification time of the given path or anything inside it, not following symlinks and skipping paths matching the given excludes, like the `session.lock` the server keeps touching.
+/// `relative_path` is the path of `path` relative to the `rsync` transfer root. Files and directories inside it which are deleted while it's being inspected are ignored.
+fn latest_modification(path: impl AsRef<Path>, excludes: Arc<Excludes>, relative_path: PathBuf) -> Pin<Box<dyn Future<Output = wheel::Result<SystemTime>>>> {
+    let path = path.as_ref().to_owned();
+    Box::pin(async move {
+        let metadata = fs::symlink_metadata(&path).await?;
+        if excludes.is_excluded(&relative_path, metadata.is_dir()) { return Ok(SystemTime::UNIX_EPOCH) }
+        let modified = metadata.modified().at(&path)?;
+        if metadata.is_dir() {
+            fs::read_dir(path)
+                .map_ok(|entry| {
+                    let excludes = excludes.clone();
+                    let relative_path = relative_path.join(entry.file_name());
+                    async move {
+                        match latest_modification(entry.path(), excludes, relative_path).await {
+                            // deleted since the directory was read, e.g. a temporary file of the running server
+                            Err(e) if is_not_found(&e) => Ok(SystemTime::UNIX_EPOCH),
+                            res => res,
+                        }
+                    }
+                })
+                .try_buffer_unordered(DIR_SIZE_CONCURRENCY)
+                .try_fold(modified, |latest, entry_modified| async move { Ok(latest.max(entry_modified)) })
+                .await
+        } else {
+            Ok(modified)
+        }
+    })
+}
+
+/// The number of files in the given path or anything inside it, not counting directories or following symlinks. Files and directories inside it which are deleted while it's being inspected don't count.
+fn file_count(path: impl AsRef<Path>) -> Pin<Box<dyn Future<Output = wheel::Result<u64>>>> {
+    let path = path.as_ref().to_owned();
+    Box::pin(async move {
+        if fs::symlink_metadata(&path).await?.is_dir() {
+            fs::read_dir(path)
+                .map_ok(|entry| async move {
+                    match file_count(entry.path()).await {
+                        // deleted since the directory was read, like in latest_modification
+                        Err(e) if is_not_found(&e) => Ok(0),
+                        res => res,
+                    }
+                })
+                .try_buffer_unordered(DIR_SIZE_CONCURRENCY)
+                .try_fold(0, |count, entry_count| async move { Ok(count + entry_count) })
+                .await
+        } else {
+            Ok(1)
+        }
+    })
+}
+
+/// Extensions of files stored next to a backup, which share the backup's filename plus the extension.
+const SIDECAR_EXTENSIONS: &[&str] = &["duplicate", "meta.json", "pinned", "sha256"];
+
+fn is_sidecar(filename: &str) -> bool {
+    SIDECAR_EXTENSIONS.iter().any(|extension| filename.strip_suffix(extension).is_some_and(|prefix| prefix.ends_with('.')))
+}
+
+/// The name under which a backup with the given filename is written until it's complete, so an interrupted backup is never mistaken for a complete one.
+fn temp_name(filename: &str) -> String {
+    format!(".{filename}.tmp")
+}
+
+fn is_temporary(filename: &str) -> bool {
+    filename.starts_with('.') && filename.ends_with(".tmp")
+}
+
+/// Whether the given file is kept directly in the backup path besides the backups, so it's next to the backups themselves in [`Layout::Flat`].
+fn is_auxiliary(filename: &str) -> bool {
+    filename.ends_with(".lock") || filename == AUDIT_LOG_FILENAME || filename == format!("{AUDIT_LOG_FILENAME}.1") || filename == "lost+found"
+}
+
+/// Removes backups of the given world left behind under their temporary name by an interrupted run, or only simulates this in `--dry-run` mode.
+async fn remove_temporaries<S: Server>(options: &Options, world: &S) -> Result<(), Error> {
+    let dir = options.store(world).dir();
+    if !fs::exists(&dir).await? { return Ok(()) }
+    let mut entries = pin!(fs::read_dir(&dir));
+    while let Some(entry) = entries.try_next().await? {
+        let filename = entry.file_name().into_string()?;
+        if !is_temporary(&filename) { continue }
+        if options.dry_run.is_some() {
+            info!("would remove incomplete backup {filename}");
+        } else {
+            info!("removing incomplete backup {filename}");
+            if entry.file_type().await.at(entry.path())?.is_dir() {
+                fs::remove_dir_all(entry.path()).await?;
+            } else {
+                fs::remove_file(entry.path()).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Messages in `tar`'s or the decompressor's error output which mean the archive itself is damaged, as opposed to e.g. a permission error.
+const CORRUPTION_MARKERS: &[&str] = &[
+    "unexpected end of file",
+    "unexpected eof",
+    "invalid compressed data",
+    "not in gzip format",
+    "does not look like a tar archive",
+    "corrupt",
+    "crc error",
+    "unknown frame descriptor",
+    "premature end",
+    "truncated",
+];
+
+/// Deletes backups of the given world which were obviously left incomplete by a crashed run: empty directories and archives which `tar` reports as damaged.
+///
+/// New backups are only renamed to their final name once complete, so this mostly finds backups created by older versions and archives from an interrupted compression.
+/// Pinned backups are never deleted, and archives are skipped if the program needed to decompress them isn't installed.
+async fn remove_partial<S: Server>(options: &Options, world: &S) -> Result<(), Error> {
+    let store = options.store(world);
+    let mut missing_programs = Vec::default();
+    for backup in store.scan().await?.0 {
+        if is_pinned(&backup.path).await? { continue }
+        let reason = if let (Some(tar_flag), Some(program)) = (backup.compression.tar_flag(), backup.compression.program()) {
+            if !on_path(program) {
+                if !missing_programs.contains(&program) {
+                    warn!("{program} not found, not checking {} backups for damage", backup.compression.description());
+                    missing_programs.push(program);
+                }
+                continue
+            }
+            match options.command("tar").arg(tar_flag).arg("-tf").arg(&backup.path).stdout(Stdio::null()).stderr(Stdio::piped()).check("tar").await {
+                Ok(_) => continue,
+                Err(wheel::Error::CommandExit { output, .. }) if {
+                    let stderr = String::from_utf8_lossy(&output.stderr).to_lowercase();
+                    CORRUPTION_MARKERS.iter().any(|marker| stderr.contains(marker))
+                } => "archive is damaged",
+                Err(e) => return Err(e.into()),
+            }
+        } else if pin!(fs::read_dir(&backup.path)).try_next().await?.is_none() {
+            "empty directory"
+        } else {
+            continue
+        };
+        warn!("removing partial backup {}: {reason}", backup.filename);
+        delete_backup(options, &store, backup.filename).await?;
+    }
+    Ok(())
+}
+
+fn sidecar_path(backup: &Path, extension: &str) -> PathBuf {
+    let mut path = backup.as_os_str().to_owned();
+    path.push(".");
+    path.push(extension);
+    PathBuf::from(path)
+}
+
+async fn remove_sidecars(backup: &Path) -> Result<(), Error> {
+    for extension in SIDECAR_EXTENSIONS {
+        let path = sidecar_path(backup, extension);
+        if fs::exists(&path).await? {
+            fs::remove_file(path).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether the given backup has been pinned using the `pin` subcommand, i.e. it has an empty `.pinned` file next to it.
+async fn is_pinned(backup: &Path) -> Result<bool, Error> {
+    Ok(fs::exists(sidecar_path(backup, "pinned")).await?)
+}
+
+/// The filename of the backup which the given backup duplicates according to its `.duplicate` file, if any (see [`dedup`]).
+async fn duplicate_of(backup: &Path) -> Result<Option<String>, Error> {
+    let path = sidecar_path(backup, "duplicate");
+    Ok(if fs::exists(&path).await? { Some(fs::read_to_string(path).await?.trim().to_owned()) } else { None })
+}
+
+/// Pins or unpins the backup of the given world with the given timestamp, so that it's never deleted automatically.
+pub async fn set_pinned<S: Server>(options: &Options, world: &S, timestamp: DateTime<Utc>, pinned: bool) -> Result<(), Error> {
+    let path = options.store(world).find(timestamp).await?.path;
+    let pin_path = sidecar_path(&path, "pinned");
+    if pinned == fs::exists(&pin_path).await? { return Ok(()) }
+    let Some(filename) = path.file_name() else { panic!("backup at root") };
+    if options.dry_run.is_some() {
+        info!("would {} {}", if pinned { "pin" } else { "unpin" }, filename.to_string_lossy());
+    } else if pinned {
+        fs::write(pin_path, "").await?;
+    } else {
+        fs::remove_file(pin_path).await?;
+    }
+    Ok(())
+}
+
+/// Computes the SHA-256 checksum of the given file without reading it into memory all at once.
+async fn sha256(path: &Path) -> Result<String, Error> {
+    let mut file = tokio::fs::File::open(path).await.at(path)?; //TODO wheel
+    let mut hasher = Sha256::default();
+    let mut buf = vec![0; 1024 * 1024];
+    loop {
+        let len = file.read(&mut buf).await.at(path)?;
+        if len == 0 { break }
+        hasher.update(&buf[..len]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+    None,
+}
+
+impl Compression {
+    /// The file extension of backups compressed using this method, or `None` for uncompressed backup directories.
+    fn extension(&self) -> Option<&'static str> {
+        match self {
+            Self::Gzip => Some("tar.gz"),
+            Self::Zstd => Some("tar.zst"),
+            Self::None => None,
+        }
+    }
+
+    /// The `tar` option selecting this compression method.
+    fn tar_flag(&self) -> Option<&'static str> {
+        match self {
+            Self::Gzip => Some("--gzip"),
+            Self::Zstd => Some("--zstd"),
+            Self::None => None,
+        }
+    }
+
+    /// The program `tar` uses for this compression method by default.
+    fn program(&self) -> Option<&'static str> {
+        match self {
+            Self::Gzip => Some("gzip"),
+            Self::Zstd => Some("zstd"),
+            Self::None => None,
+        }
+    }
+
+    /// The compression levels supported by [`Compression::program`], not counting zstd's `--ultra` levels.
+    pub fn levels(&self) -> Option<RangeInclusive<u8>> {
+        match self {
+            Self::Gzip => Some(1..=9),
+            Self::Zstd => Some(1..=19),
+            Self::None => None,
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Zstd => "zstd",
+            Self::None => "uncompressed",
+        }
+    }
+}
+
+/// Whether an executable file with the given name is in one of the directories on the `PATH`.
+pub fn on_path(name: &str) -> bool {
+    env::var_os("PATH").is_some_and(|path| env::split_paths(&path).any(|dir| dir.join(name).is_file()))
+}
+
+/// An I/O scheduling class for `ionice`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum IoPriority {
+    /// Only access the disk when no other process needs it. Backups may take much longer if the disk is busy.
+    Idle,
+    /// The lowest priority in the default scheduling class.
+    BestEffort,
+    /// Don't change the I/O priority.
+    None,
+}
+
+/// How backups are arranged in the backup path, selected using `--layout`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Layout {
+    /// The backups of each world are in a directory named after the world, so the backup path can be shared by multiple worlds.
+    #[default]
+    PerWorld,
+    /// The backups are directly in the backup path, which may then only be used for a single world.
+    Flat,
+}
+
+/// Whether the given archive compressed using the given `tar` option contains a member matching the given pattern, where `*` doesn't match `/`.
+async fn archive_contains(options: &Options, archive: &Path, tar_flag: &str, pattern: &str) -> Result<bool, Error> {
+    // tar fails if no member matches
+    Ok(options.command("tar")
+        .arg(tar_flag)
+        .arg("-tf")
+        .arg(archive)
+        .arg("--wildcards")
+        .arg("--no-wildcards-match-slash")
+        .arg(pattern)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status().await?
+        .success())
+}
+
+/// Writes a `.sha256` file next to the given archive.
+async fn write_checksum(archive: &Path) -> Result<(), Error> {
+    let checksum = sha256(archive).await?;
+    let Some(archive_name) = archive.file_name() else { panic!("archive at root") };
+    // same format as the output of `sha256sum`, so the archive can also be checked using `sha256sum -c`
+    fs::write(sidecar_path(archive, "sha256"), format!("{checksum}  {}\n", archive_name.to_str().ok_or(Error::Utf8)?)).await?;
+    Ok(())
+}
+
+/// A range of backup timestamps, given using `--since` and `--until`.
+#[derive(Clone, Copy)]
+pub struct TimeRange {
+    since: Bound<DateTime<Utc>>,
+    until: Bound<DateTime<Utc>>,
+}
+
+impl TimeRange {
+    /// Both bounds are inclusive and accept either a full timestamp in the configured format or a date like `2024-01-31`, which includes that entire day (in UTC).
+    pub fn new(timestamp_format: &TimestampFormat, since: Option<&str>, until: Option<&str>) -> Result<Self, Error> {
+        fn parse_bound(timestamp_format: &TimestampFormat, bound: &str, end: bool) -> Result<Bound<DateTime<Utc>>, Error> {
+            if let Ok(timestamp) = timestamp_format.parse(bound) { return Ok(Bound::Included(timestamp)) }
+            let date = NaiveDate::parse_from_str(bound, "%Y-%m-%d").map_err(|_| Error::InvalidDate(bound.to_owned()))?;
+            Ok(if end {
+                let next_day = date.succ_opt().ok_or_else(|| Error::InvalidDate(bound.to_owned()))?;
+                Bound::Excluded(next_day.and_hms_opt(0, 0, 0).expect("midnight is a valid time").and_utc())
+            } else {
+                Bound::Included(date.and_hms_opt(0, 0, 0).expect("midnight is a valid time").and_utc())
+            })
+        }
+
+        let since = since.map(|since| parse_bound(timestamp_format, since, false)).transpose()?.unwrap_or(Bound::Unbounded);
+        let until = until.map(|until| parse_bound(timestamp_format, until, true)).transpose()?.unwrap_or(Bound::Unbounded);
+        let inverted = match (since, until) {
+            (Bound::Included(since), Bound::Included(until)) => since > until,
+            (Bound::Included(since), Bound::Excluded(until)) => since >= until,
+            (_, _) => false,
+        };
+        if inverted { return Err(Error::InvertedRange) }
+        Ok(Self { since, until })
+    }
+
+    fn is_unbounded(&self) -> bool {
+        matches!((self.since, self.until), (Bound::Unbounded, Bound::Unbounded))
+    }
+
+    fn contains(&self, timestamp: DateTime<Utc>) -> bool {
+        (self.since, self.until).contains(&timestamp)
+    }
+}
+
+/// A Minecraft version as it appears in backup filenames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Version {
+    /// A release version like `1.20.4`, as `[major, minor, patch]`. Missing components are zero.
+    Release([i64; 3]),
+    /// A snapshot like `24w14a`, a pre-release like `1.20.5-pre1`, or any other version that isn't made of up to three numbers.
+    Snapshot,
+}
+
+impl Version {
+    fn parse(version: &str) -> Self {
+        if let Ok(mut parts) = version.split('.').map(|part| part.parse::<i64>()).try_collect::<_, Vec<_>, _>() {
+            if parts.len() <= 3 {
+                parts.resize(3, 0);
+                return Self::Release(<[_; 3]>::try_from(parts).unwrap())
+            }
+        }
+        Self::Snapshot
+    }
+}
+
+/// How [`BackupStore::delete_closest`] chooses which backup to delete, selected using `--retention-strategy`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum RetentionStrategy {
+    /// Delete the backup closest to its neighbors, where backups of different Minecraft versions are always farther apart than backups of the same version.
+    #[default]
+    VersionFirst,
+    /// Delete the backup closest to its neighbors in time, regardless of Minecraft versions, to keep the backups evenly spaced.
+    TimeFirst,
+    /// Delete the oldest backup.
+    Oldest,
+    /// Delete the newest backup.
+    Newest,
+}
+
+impl RetentionStrategy {
+    /// The backup to delete among the given ones, which are sorted by timestamp. Protected candidates are never chosen.
+    ///
+    /// With every strategy, duplicates (see [`dedup`]) are chosen before any other backups, since deleting one doesn't lose anything. Among the duplicates, the strategy chooses as usual.
+    fn victim<'a, 'b>(&self, candidates: &'b [Candidate<'a>]) -> Option<&'b Candidate<'a>> {
+        // never delete the last remaining backup
+        if candidates.len() < 2 { return None }
+        match self {
+            Self::VersionFirst => closest(candidates.iter().sorted_by(|candidate1, candidate2| (candidate1.release, candidate1.timestamp, &candidate1.filename).cmp(&(candidate2.release, candidate2.timestamp, &candidate2.filename))).collect(), version_distance),
+            Self::TimeFirst => closest(candidates.iter().collect(), |prev, next| next.timestamp - prev.timestamp),
+            Self::Oldest => candidates.iter().find(|candidate| !candidate.protected && candidate.duplicate).or_else(|| candidates.iter().find(|candidate| !candidate.protected)),
+            Self::Newest => candidates.iter().rev().find(|candidate| !candidate.protected && candidate.duplicate).or_else(|| candidates.iter().rev().find(|candidate| !candidate.protected)),
+        }
+    }
+}
+
+/// A backup which [`BackupStore::delete_closest`] may delete.
+struct Candidate<'a> {
+    /// The backup's Minecraft release, or for snapshots the release of the preceding backup, since they have no known position among releases.
+    release: [i64; 3],
+    timestamp: DateTime<Utc>,
+    filename: &'a str,
+    /// Whether the backup is pinned or protected (see [`Options::protected`]). Protected backups are never deleted, but still count as neighbors of the other backups.
+    protected: bool,
+    /// Whether the backup has the same contents as the preceding backup (see [`dedup`]), so it's deleted before any backup which isn't a duplicate.
+    duplicate: bool,
+}
+
+/// The unprotected backup closest to its neighbors in the given order according to the given distance, where the first and last backups only have one neighbor and are never chosen.
+/// Duplicates are chosen before any other backups regardless of distance, including the first and last backups.
+///
+/// If there are only two backups or only the first and last are unprotected, the first unprotected backup is chosen instead.
+fn closest<'a, 'b, D: Ord>(candidates: Vec<&'b Candidate<'a>>, distance: impl Fn(&Candidate<'_>, &Candidate<'_>) -> D) -> Option<&'b Candidate<'a>> {
+    let first_unprotected = |duplicate: bool| candidates.iter().copied().find(|candidate| !candidate.protected && (candidate.duplicate || !duplicate));
+    if candidates.len() <= 2 { return first_unprotected(true).or_else(|| first_unprotected(false)) }
+    match candidates.iter().copied().tuple_windows().filter(|&(_, curr, _)| !curr.protected).min_by_key(|&(prev, curr, next)| {
+        let mut distances = [distance(prev, curr), distance(curr, next)];
+        distances.sort();
+        (!curr.duplicate, distances)
+    }).map(|(_, curr, _)| curr) {
+        Some(victim) if victim.duplicate => Some(victim),
+        // a duplicate can still be the first or last backup
+        victim => first_unprotected(true).or(victim).or_else(|| first_unprotected(false)),
+    }
+}
+
+/// The distance between two backups sorted by release and then timestamp, compared by major version, then minor version, then patch, then time.
+fn version_distance(old: &Candidate<'_>, new: &Candidate<'_>) -> (i64, i64, i64, chrono::Duration) {
+    let [old_major, old_minor, old_patch] = old.release;
+    let [new_major, new_minor, new_patch] = new.release;
+    let major_distance = new_major - old_major;
+    let minor_distance = if new_major == old_major { new_minor - old_minor } else { 0 };
+    let patch_distance = if new_major == old_major && new_minor == old_minor { new_patch - old_patch } else { 0 };
+    (major_distance, minor_distance, patch_distance, new.timestamp - old.timestamp)
+}
+
+/// The filename of the backup to delete among the given ones, which are sorted by timestamp, or `None` if none should be deleted. Backups for which `is_protected` returns `true` are never chosen.
+/// Backups for which `is_duplicate` returns `true` are chosen first.
+///
+/// This doesn't access the file system, so it can also be used to preview which backup would be deleted.
+fn choose_victim<'a>(strategy: RetentionStrategy, entries: &'a [(Version, DateTime<Utc>, String)], is_protected: impl Fn(DateTime<Utc>) -> bool, is_duplicate: impl Fn(DateTime<Utc>) -> bool) -> Option<&'a str> {
+    let mut last_release = [0; 3];
+    let candidates = entries.iter().map(|(version, timestamp, filename)| {
+        let release = match *version {
+            Version::Release(release) => {
+                last_release = release;
+                release
+            }
+            Version::Snapshot => last_release,
+        };
+        Candidate { release, timestamp: *timestamp, filename, protected: is_protected(*timestamp), duplicate: is_duplicate(*timestamp) }
+    }).collect_vec();
+    strategy.victim(&candidates).map(|candidate| candidate.filename)
+}
+
+/// A backup found in a [`BackupStore`].
+pub struct BackupInfo {
+    pub path: PathBuf,
+    pub filename: String,
+    pub timestamp: DateTime<Utc>,
+    pub version: String,
+    pub compression: Compression,
+}
+
+/// The backups of a world, stored in `<backup path>/<world>` (or directly in the backup path with [`Layout::Flat`]) as directories or archives named like `<timestamp>_<version>`, possibly with sidecar files next to them.
+pub struct BackupStore<'a, S> {
+    pub path: &'a Path,
+    pub layout: Layout,
+    pub timestamp_format: &'a TimestampFormat,
+    pub world: &'a S,
+    /// In `--dry-run` mode, backups which would already have been deleted or compressed are skipped.
+    dry_run: Option<&'a RefCell<DryRun>>,
+}
+
+impl<'a, S: Server> BackupStore<'a, S> {
+    pub fn new(path: &'a Path, layout: Layout, timestamp_format: &'a TimestampFormat, world: &'a S) -> Self {
+        Self { path, layout, timestamp_format, world, dry_run: None }
+    }
+
+    /// The directory containing the backups.
+    pub fn dir(&self) -> PathBuf {
+        match self.layout {
+            Layout::PerWorld => self.path.join(self.world.to_string()),
+            Layout::Flat => self.path.to_owned(),
+        }
+    }
+
+    /// All backups sorted by timestamp, along with the filenames of the entries not matching the filename format and why they don't.
+    ///
+    /// Sidecar files and backups which are still being written are skipped, as are lock files and the audit log with [`Layout::Flat`]. If the world hasn't been backed up yet, there are no backups.
+    pub async fn scan(&self) -> Result<(Vec<BackupInfo>, Vec<(String, Error)>), Error> {
+        let dir = self.dir();
+        let mut backups = Vec::default();
+        let mut invalid = Vec::default();
+        if !fs::exists(&dir).await? { return Ok((backups, invalid)) }
+        let mut entries = pin!(fs::read_dir(&dir));
+        while let Some(entry) = entries.try_next().await? {
+            let path = entry.path();
+            if self.dry_run.is_some_and(|dry_run| dry_run.borrow().handled.contains(&path)) { continue }
+            let filename = entry.file_name().into_string()?;
+            if is_sidecar(&filename) || is_temporary(&filename) || self.layout == Layout::Flat && is_auxiliary(&filename) { continue }
+            match self.timestamp_format.parse_filename(&filename) {
+                Ok((timestamp, version, compression)) => {
+                    let version = version.to_owned();
+                    backups.push(BackupInfo { path, filename, timestamp, version, compression });
+                }
+                Err(e) => invalid.push((filename, e)),
+            }
+        }
+        backups.sort_by(|backup1, backup2| backup1.timestamp.cmp(&backup2.timestamp).then_with(|| backup1.filename.cmp(&backup2.filename)));
+        Ok((backups, invalid))
+    }
+
+    /// All backups sorted by timestamp. Fails with [`Error::FilenameFormat`] if there's anything else in the directory, to avoid deleting backups based on an incomplete picture.
+    pub async fn list(&self) -> Result<Vec<BackupInfo>, Error> {
+        let (backups, invalid) = self.scan().await?;
+        if let Some((_, e)) = invalid.into_iter().next() { return Err(e) }
+        Ok(backups)
+    }
+
+    /// The most recent backup, if any. Entries not matching the filename format are ignored.
+    pub async fn newest(&self) -> Result<Option<BackupInfo>, Error> {
+        Ok(self.scan().await?.0.pop())
+    }
+
+    /// The backup with the given timestamp.
+    pub async fn find(&self, timestamp: DateTime<Utc>) -> Result<BackupInfo, Error> {
+        let mut found = None;
+        for backup in self.scan().await?.0 {
+            if backup.timestamp == timestamp {
+                if found.is_some() { return Err(Error::AmbiguousTimestamp(self.timestamp_format.display(timestamp).to_string())) }
+                found = Some(backup);
+            }
+        }
+        found.ok_or_else(|| Error::NoSuchBackup(self.timestamp_format.display(timestamp).to_string()))
+    }
+
+    /// The backup whose timestamp in the configured format is or starts with the given one, e.g. `2024-06-01` for the only backup from that day.
+    ///
+    /// Fails with [`Error::AmbiguousPrefix`], listing the candidates, if more than one backup matches.
+    pub async fn find_prefix(&self, prefix: &str) -> Result<BackupInfo, Error> {
+        if let Ok(timestamp) = self.timestamp_format.parse(prefix) { return self.find(timestamp).await }
+        let mut candidates = self.scan().await?.0.into_iter().filter(|backup| self.timestamp_format.display(backup.timestamp).to_string().starts_with(prefix)).collect_vec();
+        match candidates.len() {
+            0 => Err(Error::NoMatchingBackup(prefix.to_owned())),
+            1 => Ok(candidates.pop().unwrap()),
+            _ => Err(Error::AmbiguousPrefix { prefix: prefix.to_owned(), candidates: candidates.into_iter().map(|backup| backup.filename).collect() }),
+        }
+    }
+
+    /// The `n`th most recent backup, where 1 is the newest, if there are at least `n` backups. Entries not matching the filename format are ignored.
+    pub async fn nth_newest(&self, n: usize) -> Result<Option<BackupInfo>, Error> {
+        Ok(self.scan().await?.0.into_iter().rev().nth(n.saturating_sub(1)))
+    }
+
+    /// The least recent backup, if any. Entries not matching the filename format are ignored.
+    pub async fn oldest(&self) -> Result<Option<BackupInfo>, Error> {
+        Ok(self.scan().await?.0.into_iter().next())
+    }
+
+    /// The total size of the backups, not including sidecar files.
+    pub async fn total_size(&self) -> Result<ByteSize, Error> {
+        let mut total_size = ByteSize::default();
+        for backup in self.list().await? {
+            total_size += dir_size(&backup.path).await?;
+        }
+        Ok(total_size)
+    }
+
+    /// Deletes the backup chosen by `--retention-strategy`, by default the one that's closest to other backups. In case of a tie, the oldest backup is deleted.
+    ///
+    /// Pinned backups, protected backups (see [`Options::protected`]), and the `--keep-last` newest backups are never deleted, but still count as neighbors of the other backups.
+    /// Backups with the same timestamp and version are at distance zero from each other, so one of them (the one whose filename sorts first) is deleted first.
+    /// If only one backup exists or all candidates are pinned or protected, nothing is deleted and `false` is returned.
+    pub async fn delete_closest(&self, options: &Options) -> Result<bool, Error> {
+        let backups = self.list().await?;
+        if !backups.iter().map(|backup| (backup.timestamp, &backup.version)).all_unique() {
+            warn!("multiple backups of the {} world have the same timestamp and version", self.world);
+        }
+        let mut pinned = HashSet::new();
+        let mut duplicates = HashSet::new();
+        let mut entries = Vec::<(_, _, String)>::with_capacity(backups.len());
+        for BackupInfo { path, filename, timestamp, version, .. } in backups {
+            if is_pinned(&path).await? { pinned.insert(timestamp); }
+            // only a duplicate of the immediately preceding backup, since otherwise the backup in between might have been the duplicate's only neighbor with the same contents
+            if let Some(original) = duplicate_of(&path).await? {
+                if entries.last().is_some_and(|(_, _, prev)| *prev == original) { duplicates.insert(timestamp); }
+            }
+            entries.push((Version::parse(&version), timestamp, filename));
+        }
+        let kept = entries.iter().rev().take(options.keep_last).map(|&(_, timestamp, _)| timestamp).collect::<HashSet<_>>();
+        let Some(victim) = choose_victim(options.retention_strategy, &entries, |timestamp| pinned.contains(&timestamp) || kept.contains(&timestamp) || options.protected.borrow().contains(&timestamp), |timestamp| duplicates.contains(&timestamp)) else { return Ok(false) };
+        delete_backup(options, self, victim.to_owned()).await?;
+        Ok(true)
+    }
+}
+
+/// Options shared by the operations on the backup directory.
+pub struct Options {
+    pub backup_path: PathBuf,
+    /// Whether each world's backups are in their own directory in the backup path.
+    pub layout: Layout,
+    /// The format of the timestamps in backup filenames.
+    pub timestamp_format: TimestampFormat,
+    /// The backup path as an rsync destination like `user@host:/path` if it's on a remote host, in which case most operations aren't supported.
+    pub remote: Option<String>,
+    pub compression: Compression,
+    /// Per-world settings from the config file.
+    pub worlds: HashMap<String, WorldConfig>,
+    /// Overrides the world directory of the (only) world.
+    pub world_dir: Option<PathBuf>,
+    /// Overrides the server jar of the (only) world, used to determine its Minecraft version.
+    pub jar_path: Option<PathBuf>,
+    /// Paths in the world directory which aren't backed up.
+    pub excludes: Arc<Excludes>,
+    /// The names and directories of the dimensions excluded using `--skip-dimension`, which must also be part of `excludes`.
+    pub skip_dimensions: Vec<(String, PathBuf)>,
+    /// Whether to hardlink unchanged files to the previous backup instead of copying them.
+    pub incremental: bool,
+    /// Whether to archive the world directly instead of copying it first and compressing the copy later.
+    pub stream_compress: bool,
+    /// The maximum number of backups compressed at the same time.
+    pub compress_jobs: usize,
+    /// The number of most recent backups of each world which aren't compressed.
+    pub keep_uncompressed: usize,
+    /// Whether [`make_room`] compresses backups before deleting any.
+    pub compress_before_delete: bool,
+    /// A program like `pigz` that `tar` uses to compress new backups instead of the default for the compression method.
+    pub compress_program: Option<String>,
+    /// Passed to the compression program as an option like `-9`.
+    pub compression_level: Option<u8>,
+    /// Whether to create a backup even if the world hasn't changed since the last one.
+    pub force: bool,
+    /// If the newest backup of a world is younger than this, no new one is created unless `force` is set.
+    pub min_interval: Option<chrono::Duration>,
+    /// Whether to delete obviously incomplete backups before backing up.
+    pub clean_partial: bool,
+    /// Overrides the timestamp of the new backup, which is otherwise the current time.
+    pub timestamp: Option<DateTime<Utc>>,
+    /// Whether to write a `.sha256` file next to each newly compressed backup.
+    pub checksum: bool,
+    /// Whether to check that newly compressed archives contain the world's `level.dat` before deleting the uncompressed backup.
+    pub verify_compressed: bool,
+    /// How long `rsync` and `tar` may run before they're killed.
+    pub command_timeout: Option<Duration>,
+    /// Whether to sync the world's file system after disabling saves.
+    pub sync: bool,
+    /// Whether to disable saves while the world is being backed up or restored. If `false`, no commands are sent to the server.
+    pub toggle_saves: bool,
+    /// Whether to fail with [`Error::ServerStopped`] instead of backing up a world whose server is stopped without toggling saves, unless `toggle_saves` is `false`.
+    pub server_check: bool,
+    /// If given, the world is backed up from a snapshot of its file system, so saves only need to be off while the snapshot is created.
+    pub snapshot: Option<SnapshotKind>,
+    /// The world directory inside the snapshot of the world currently being backed up in `--snapshot` mode.
+    pub snapshot_dir: RefCell<Option<PathBuf>>,
+    /// How many times `rsync` is run while it still finds changes before the backup is considered done.
+    pub max_rsync_passes: usize,
+    /// The niceness of `rsync` and `tar`.
+    pub nice: i64,
+    /// The I/O scheduling class of `rsync` and `tar`.
+    pub ionice: IoPriority,
+    /// The maximum rate at which `rsync` transfers data, per second.
+    pub bwlimit: Option<ByteSize>,
+    /// The space which must remain free on the backup mount after creating a backup.
+    pub min_free_bytes: ByteSize,
+    /// The percentage of the backup mount which must remain free after creating a backup.
+    pub min_free_percent: f64,
+    /// Backups aren't started if they would fill more than this percentage of the backup mount.
+    pub max_disk_usage_percent: Option<f64>,
+    /// In `--dry-run` mode, commands and deletions are only printed, and their effects are simulated here.
+    pub dry_run: Option<RefCell<DryRun>>,
+    /// The filenames of the backups deleted during this run.
+    pub deleted: RefCell<Vec<String>>,
+    /// How [`delete_one`] chooses which backup to delete.
+    pub retention_strategy: RetentionStrategy,
+    /// The number of most recent backups of each world which [`delete_one`] must not delete, in addition to those in `protected`.
+    pub keep_last: usize,
+    /// Timestamps of backups which [`delete_one`] must not delete, such as the one currently being created.
+    pub protected: RefCell<HashSet<DateTime<Utc>>>,
+    /// Where backups which are created, deleted, or compressed are recorded, unless disabled in the config.
+    pub audit_log: Option<AuditLog>,
+}
+
+/// The space which must be available on a disk of the given total size to write `amount` while leaving both `min_free_bytes` and `min_free_percent` % of the disk free.
+fn required_space_on(total: ByteSize, amount: ByteSize, min_free_bytes: ByteSize, min_free_percent: f64) -> ByteSize {
+    let min_free_percent = ByteSize::b((total.as_u64() as f64 * min_free_percent / 100.0).ceil() as u64);
+    amount + min_free_bytes.max(min_free_percent)
+}
+
+/// Whether `amount` can be written to a disk of the given total size with the given available space while leaving both `min_free_bytes` and `min_free_percent` % of the disk free.
+fn has_room_on(total: ByteSize, available: ByteSize, amount: ByteSize, min_free_bytes: ByteSize, min_free_percent: f64) -> bool {
+    available >= required_space_on(total, amount, min_free_bytes, min_free_percent)
+}
+
+/// The simulated effects of a `--dry-run`, so that loops waiting for those effects still terminate.
+#[derive(Default)]
+pub struct DryRun {
+    /// Backups which would have been deleted or compressed.
+    handled: HashSet<PathBuf>,
+    /// Disk space which would have been freed by deleting backups.
+    freed: ByteSize,
+}
+
+impl Options {
+    /// The file system the backup path is on.
+    fn mount(&self) -> Result<Filesystem, Error> {
+        self.backup_path.ancestors().map(|ancestor| System::new().mount_at(ancestor)).find_map(Result::ok).ok_or(Error::NoMount)
+    }
+
+    /// Fails with [`Error::Unmounted`] if the backup path is on the root file system, which usually means the backup drive isn't mounted.
+    pub fn check_mounted(&self) -> Result<(), Error> {
+        let mount_point = self.backup_path.ancestors().find(|ancestor| System::new().mount_at(ancestor).is_ok()).ok_or(Error::NoMount)?;
+        if mount_point.parent().is_none() { return Err(Error::Unmounted(self.backup_path.clone())) }
+        Ok(())
+    }
+
+    /// The space available on the backup mount, including any space which would have been freed in `--dry-run` mode.
+    fn available_space(&self) -> Result<ByteSize, Error> {
+        Ok(self.mount()?.avail + self.dry_run.as_ref().map_or_else(ByteSize::default, |dry_run| dry_run.borrow().freed))
+    }
+
+    /// Whether `amount` can be written to the backup mount while leaving both `--min-free-bytes` and `--min-free-percent` free. See [`has_room_on`].
+    fn has_room(&self, amount: ByteSize) -> Result<bool, Error> {
+        Ok(has_room_on(self.mount()?.total, self.available_space()?, amount, self.min_free_bytes, self.min_free_percent))
+    }
+
+    /// The space which must be available on the backup mount to write `amount` while leaving both `--min-free-bytes` and `--min-free-percent` free. See [`required_space_on`].
+    fn required_space(&self, amount: ByteSize) -> Result<ByteSize, Error> {
+        Ok(required_space_on(self.mount()?.total, amount, self.min_free_bytes, self.min_free_percent))
+    }
+
+    /// In `--verbose` mode, logs the space available on the backup mount, both absolute and as a percentage of the disk.
+    fn report_free_space(&self, when: &str) -> Result<(), Error> {
+        if tracing::enabled!(Level::DEBUG) {
+            let total = self.mount()?.total;
+            let avail = self.available_space()?;
+            debug!("{avail} ({:.1}%) free on the backup drive {when}", avail.as_u64() as f64 / total.as_u64().max(1) as f64 * 100.0);
+        }
+        Ok(())
+    }
+
+    /// The backups of the given world, skipping those which would already have been deleted or compressed in `--dry-run` mode.
+    pub fn store<'a, S: Server>(&'a self, world: &'a S) -> BackupStore<'a, S> {
+        BackupStore { path: &self.backup_path, layout: self.layout, timestamp_format: &self.timestamp_format, world, dry_run: self.dry_run.as_ref() }
+    }
+
+    /// Whether the given backup would already have been deleted or compressed in `--dry-run` mode.
+    fn is_handled(&self, path: &Path) -> bool {
+        self.dry_run.as_ref().is_some_and(|dry_run| dry_run.borrow().handled.contains(path))
+    }
+
+    /// In `--dry-run` mode, records that the given backup would have been deleted or compressed, freeing the given amount of space.
+    fn record_handled(&self, path: PathBuf, freed: ByteSize) {
+        if let Some(ref dry_run) = self.dry_run {
+            let mut dry_run = dry_run.borrow_mut();
+            dry_run.handled.insert(path);
+            dry_run.freed += freed;
+        }
+    }
+
+    /// Appends a line to the audit log recording that the backup at the given path was created, deleted, or compressed. Must be called before deleting the backup.
+    ///
+    /// Nothing is recorded in `--dry-run` mode.
+    async fn audit<S: Server>(&self, action: &str, world: &S, path: &Path) -> Result<(), Error> {
+        let Some(ref audit_log) = self.audit_log else { return Ok(()) };
+        if self.dry_run.is_some() { return Ok(()) }
+        let Some(filename) = path.file_name() else { panic!("backup at root") };
+        let size = dir_size(path).await?;
+        audit_log.append(&format!("{}\t{action}\t{world}\t{}\t{}", Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true), filename.to_string_lossy(), size.as_u64()))
+    }
+
+    /// A command running the given program at the priority given by `--nice` and `--ionice`, for programs like `rsync` and `tar` which use a lot of CPU time or disk I/O.
+    ///
+    /// `nice` and `ionice` are skipped if they're not installed.
+    fn command(&self, program: &str) -> Command {
+        let mut wrappers = Vec::default();
+        if self.nice != 0 && on_path("nice") { wrappers.extend([String::from("nice"), String::from("-n"), self.nice.to_string()]) }
+        if on_path("ionice") {
+            match self.ionice {
+                IoPriority::Idle => wrappers.extend([String::from("ionice"), String::from("--class"), String::from("idle")]),
+                IoPriority::BestEffort => wrappers.extend([String::from("ionice"), String::from("--class"), String::from("best-effort"), String::from("--classdata"), String::from("7")]),
+                IoPriority::None => {}
+            }
+        }
+        let mut command = Command::new(wrappers.first().map_or(program, String::as_str));
+        if !wrappers.is_empty() { command.args(&wrappers[1..]).arg(program); }
+        command
+    }
+
+    /// An `rsync` command using [`Options::command`], limited to `--bwlimit` if given.
+    fn rsync(&self) -> Command {
+        let mut command = self.command("rsync");
+        if let Some(bwlimit) = self.bwlimit {
+            // rsync's suffixes are powers of 1024 by default
+            command.arg(format!("--bwlimit={}K", bwlimit.as_u64().div_ceil(1024)));
+        }
+        command
+    }
+
+    /// The directory containing the given world's files, which is only different from [`Server::server_dir`] if `--world-dir` is given.
+    pub fn world_dir<S: Server>(&self, world: &S) -> PathBuf {
+        self.world_dir.clone().unwrap_or_else(|| world.server_dir())
+    }
+
+    /// The directory the given world is read from when backing it up, which is only different from [`Options::world_dir`] in `--snapshot` mode. It has the same name either way.
+    fn source_dir<S: Server>(&self, world: &S) -> PathBuf {
+        self.snapshot_dir.borrow().clone().unwrap_or_else(|| self.world_dir(world))
+    }
+
+    /// The `tar` option selecting how to compress new backups, using `--compress-program` if there is one.
+    fn compress_flag(&self) -> Option<String> {
+        if self.compression == Compression::None { return None }
+        match (&self.compress_program, self.compression_level) {
+            (Some(compress_program), Some(level)) => Some(format!("--use-compress-program={compress_program} -{level}")),
+            (Some(compress_program), None) => Some(format!("--use-compress-program={compress_program}")),
+            (None, Some(level)) => self.compression.program().map(|program| format!("--use-compress-program={program} -{level}")),
+            (None, None) => self.compression.tar_flag().map(str::to_owned),
+        }
+    }
+
+    /// Runs the given future, failing with [`Error::Timeout`] if it takes longer than `--command-timeout`.
+    async fn with_timeout<T>(&self, name: &'static str, f: impl Future<Output = Result<T, Error>>) -> Result<T, Error> {
+        if let Some(command_timeout) = self.command_timeout {
+            timeout(command_timeout, f).await.map_err(|_| Error::Timeout(name))?
+        } else {
+            f.await
+        }
+    }
+
+    /// Runs the given command, or only prints it in `--dry-run` mode.
+    async fn run(&self, command: &mut Command, name: &'static str) -> Result<Option<Output>, Error> {
+        if self.dry_run.is_some() {
+            info!("would run {:?}", command.as_std());
+            Ok(None)
+        } else {
+            // kill the child process if the operation is cancelled, e.g. by a signal or timeout
+            Ok(Some(self.with_timeout(name, async { Ok(command.kill_on_drop(true).check(name).await?) }).await?))
+        }
+    }
+}
+
+/// An exclusive lock on the backups of a world, which is released when this is dropped.
+///
+/// The lock is held using `flock`, so it's also released if the process crashes. The lock file itself is left behind, and contains the PID of the process that last held the lock.
+pub struct Lock {
+    _file: std::fs::File,
+}
+
+impl Lock {
+    /// Locks `<backup path>/<world>.lock`, failing with [`Error::AlreadyRunning`] if another process holds the lock.
+    pub fn acquire<S: Server>(backup_path: &Path, world: &S) -> Result<Self, Error> {
+        let path = backup_path.join(format!("{world}.lock"));
+        let mut file = std::fs::OpenOptions::new().read(true).write(true).create(true).open(&path).at(&path)?;
+        match file.try_lock_exclusive() {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                let mut pid = String::default();
+                file.read_to_string(&mut pid).at(&path)?;
+                return Err(Error::AlreadyRunning { world: world.to_string(), pid: pid.trim().parse().ok() })
+            }
+            Err(e) => return Err(e).at(&path).map_err(Error::from),
+        }
+        file.set_len(0).at(&path)?;
+        write!(file, "{}", std::process::id()).at(&path)?;
+        Ok(Self { _file: file })
+    }
+}
+
+/// A tab-separated log file with a line for each backup created, deleted, or compressed, containing the current time, the action, the world, the backup's filename, and its size in bytes.
+pub struct AuditLog {
+    pub path: PathBuf,
+    /// Once the log reaches this size, it's moved to `<path>.1`, replacing the previous rotated log.
+    pub max_size: ByteSize,
+}
+
+impl AuditLog {
+    fn append(&self, line: &str) -> Result<(), Error> {
+        match std::fs::metadata(&self.path) {
+            Ok(metadata) => if metadata.len() >= self.max_size.as_u64() {
+                let mut rotated = self.path.clone().into_os_string();
+                rotated.push(".1");
+                std::fs::rename(&self.path, &rotated).at(&self.path)?;
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).at(&self.path).map_err(Error::from),
+        }
+        let mut file = std::fs::OpenOptions::new().append(true).create(true).open(&self.path).at(&self.path)?;
+        writeln!(file, "{line}").at(&self.path)?;
+        Ok(())
+    }
+}
+
+/// Deletes a backup of the given world chosen by `--retention-strategy`. See [`BackupStore::delete_closest`].
+async fn delete_one<S: Server>(options: &Options, world: &S) -> Result<bool, Error> {
+    options.store(world).delete_closest(options).await
+}
+
+/// Deletes the backup with the given filename from the given store along with its sidecar files, or only simulates this in `--dry-run` mode.
+///
+/// If the backup is a symlink, e.g. to a backup moved to an archive disk, only the symlink is removed and its target is left alone.
+/// The target may be managed separately, and deleting it wouldn't free any space on the backup drive.
+async fn delete_backup<S: Server>(options: &Options, store: &BackupStore<'_, S>, filename: String) -> Result<(), Error> {
+    if options.dry_run.is_some() {
+        info!("would delete {filename}");
+    } else {
+        debug!("deleting {filename}");
+    }
+    options.deleted.borrow_mut().push(filename.clone());
+    let path = store.dir().join(filename);
+    options.audit("deleted", store.world, &path).await?;
+    if options.dry_run.is_some() {
+        let size = dir_size(&path).await?;
+        options.record_handled(path, size);
+    } else {
+        let metadata = fs::symlink_metadata(&path).await?;
+        if metadata.is_symlink() {
+            info!("{} is a symlink, removing only the symlink and not its target {}", path.display(), fs::read_link(&path).await?.display());
+            fs::remove_file(&path).await?;
+        } else if metadata.is_dir() {
+            fs::remove_dir_all(&path).await?;
+        } else {
+            fs::remove_file(&path).await?;
+        }
+        remove_sidecars(&path).await?;
+    }
+    Ok(())
+}
+
+/// The server jar of the given world: `--jar-path` if given, otherwise the world's `minecraft_server.jar`. If the jar is a symlink, its target is returned.
+async fn server_jar<S: Server>(options: &Options, world: &S) -> Result<PathBuf, Error> {
+    let jar_path = options.jar_path.clone().unwrap_or_else(|| world.server_dir().join("minecraft_server.jar"));
+    Ok(if fs::symlink_metadata(&jar_path).await?.is_symlink() { fs::read_link(&jar_path).await? } else { jar_path })
+}
+
+/// The part of a `version.json` file we're interested in. This file is included in vanilla server jars since 18w47b.
+#[derive(Deserialize)]
+struct VersionJson {
+    id: String,
+}
+
+/// The Minecraft version in a server jar filename like `minecraft_server.1.20.4.jar` or `minecraft_server.24w14a.jar`.
+fn jar_version(filename: &str) -> Option<&str> {
+    filename.strip_prefix("minecraft_server.")?.strip_suffix(".jar").filter(|version| !version.is_empty())
+}
+
+/// Determines the Minecraft version of the given world. The following are tried in order:
+///
+/// 1. The filename of the server jar (see [`server_jar`]), if it's named like `minecraft_server.1.20.4.jar` or `minecraft_server.24w14a.jar`.
+/// 2. A `version.json` file in the world directory.
+/// 3. The `version.json` file inside the server jar, if `unzip` is available.
+async fn world_version<S: Server>(options: &Options, world: &S) -> Result<String, Error> {
+    let jar_path = server_jar(options, world).await?;
+    if let Some(version) = jar_path.file_name().and_then(|filename| filename.to_str()).and_then(jar_version) { return Ok(version.to_owned()) }
+    let version_json_path = options.world_dir(world).join("version.json");
+    if fs::exists(&version_json_path).await? {
+        let VersionJson { id } = serde_json::from_str(&fs::read_to_string(version_json_path).await?)?;
+        return Ok(id)
+    }
+    if on_path("unzip") {
+        if let Ok(output) = Command::new("unzip").arg("-p").arg(&jar_path).arg("version.json").check("unzip").await {
+            if let Ok(VersionJson { id }) = serde_json::from_slice(&output.stdout) { return Ok(id) }
+        }
+    }
+    Err(Error::JarPath(jar_path))
+}
+
+/// The numbers of files changed by an `rsync` run, not counting directories or files with only changed attributes.
+struct ItemizedChanges {
+    added: usize,
+    updated: usize,
+    deleted: usize,
+}
+
+impl ItemizedChanges {
+    /// Parses the output of `rsync --itemize-changes`, where each line looks like `>f+++++++++ path` for a new file, `>f.st...... path` for an updated file, or `*deleting   path`.
+    fn parse(output: &str) -> Self {
+        let mut changes = Self { added: 0, updated: 0, deleted: 0 };
+        for line in output.lines() {
+            if let Some(path) = line.strip_prefix("*deleting") {
+                if !path.ends_with('/') { changes.deleted += 1 }
+            } else if let Some((flags, _)) = line.split_once(' ') {
+                let mut flags = flags.chars();
+                if let (Some('>' | 'c'), Some('f')) = (flags.next(), flags.next()) {
+                    if flags.as_str().starts_with('+') { changes.added += 1 } else { changes.updated += 1 }
+                }
+            }
+        }
+        changes
+    }
+}
+
+/// How many times `rsync` is retried with exponential backoff if it reports a partial transfer.
+const RSYNC_MAX_RETRIES: u32 = 5;
+
+/// Returns the name of the created backup directory, or in `--stream-compress` mode, the name of the created archive without its extension.
+pub async fn make_backup<S: Server>(options: &Options, world: &S) -> Result<String, Error> {
+    let version = world_version(options, world).await?;
+    let store = BackupStore::new(&options.backup_path, options.layout, &options.timestamp_format, world);
+    let dir = store.dir();
+    let existing = store.scan().await?.0.into_iter().map(|backup| backup.timestamp).collect::<HashSet<_>>();
+    let now = if let Some(timestamp) = options.timestamp {
+        // round to the precision of the timestamp format
+        let timestamp = options.timestamp_format.parse(&options.timestamp_format.display(timestamp).to_string())?;
+        if existing.contains(&timestamp) { return Err(Error::DuplicateTimestamp(options.timestamp_format.display(timestamp).to_string())) }
+        timestamp
+    } else {
+        // two backups with the same timestamp would be ambiguous, so wait for the timestamp to change (at most a second with the default format)
+        loop {
+            let now = options.timestamp_format.parse(&options.timestamp_format.display(Utc::now()).to_string())?;
+            if !existing.contains(&now) { break now }
+            debug!("a backup with timestamp {} already exists, waiting", options.timestamp_format.display(now));
+            sleep(Duration::from_millis(100)).await;
+        }
+    };
+    let name = format!("{}_{version}", options.timestamp_format.display(now));
+    let link_dest = if options.incremental {
+        options.store(world).newest().await?.filter(|newest| newest.compression == Compression::None).map(|newest| newest.path)
+    } else {
+        None
+    };
+    debug!("backing up {world} world");
+    let start = Instant::now();
+    if let (true, Some(extension), Some(tar_flag)) = (options.stream_compress, options.compression.extension(), options.compress_flag()) {
+        let world_dir = options.source_dir(world);
+        let Some(world_dir_name) = world_dir.file_name() else { panic!("world directory at root") };
+        let Some(world_parent) = world_dir.parent() else { panic!("world directory at root") };
+        let archive = dir.join(format!("{name}.{extension}"));
+        let temp_archive = dir.join(temp_name(&format!("{name}.{extension}")));
+        let mut command = options.command("tar");
+        command
+            .arg(&tar_flag)
+            .arg("-cf")
+            .arg(&temp_archive)
+            .arg("--sort=name")
+            // same layout as archives created by compress_all, but without prefixing symlink targets
+            .arg(format!("--transform=s,^,{name}/,S"))
+            // tar's pattern syntax is close enough to rsync's for simple patterns
+            .args(options.excludes.patterns.iter().map(|pattern| format!("--exclude={}", pattern.trim_start_matches('/').trim_end_matches('/'))))
+            .arg("-C")
+            .arg(world_parent)
+            .arg(world_dir_name);
+        let size = world_size(options, world).await?;
+        if options.dry_run.is_none() && tracing::enabled!(Level::DEBUG) {
+            options.with_timeout("tar", tar_with_progress(&mut command, &format!("{name}.{extension}"), size)).await?;
+        } else {
+            options.run(&mut command, "tar").await?;
+        }
+        if options.dry_run.is_none() {
+            if !archive_contains(options, &temp_archive, &tar_flag, &format!("{name}/{}/level.dat", world_dir_name.to_str().ok_or(Error::Utf8)?)).await? { return Err(Error::IncompleteWorld(temp_archive)) }
+            fs::rename(&temp_archive, &archive).await?;
+            if options.checksum { write_checksum(&archive).await? }
+            Metadata {
+                minecraft_version: version,
+                server_jar: server_jar(options, world).await?,
+                duration_secs: start.elapsed().as_secs_f64(),
+                hostname: fs::read_to_string("/proc/sys/kernel/hostname").await?.trim().to_owned(),
+                file_count: file_count(&world_dir).await?,
+                uncompressed_size: Some(size.as_u64()),
+            }.write(&archive).await?;
+            options.audit("created", world, &archive).await?;
+        }
+        return Ok(name)
+    }
+    let mut num_failures = 0;
+    let mut num_passes = 0;
+    loop {
+        match options.run(options.rsync()
+            .arg("--delete")
+            .arg("--archive")
+            .arg("--itemize-changes")
+            .args(options.excludes.patterns.iter().flat_map(|pattern| ["--exclude", pattern.as_str()]))
+            .args(link_dest.iter().flat_map(|link_dest| [OsStr::new("--link-dest"), link_dest.as_os_str()]))
+            .arg(options.source_dir(world))
+            .arg(dir.join(temp_name(&name))), "rsync").await
+        {
+            Ok(None) => break,
+            Ok(Some(output)) => {
+                let stdout = String::from_utf8_lossy(&output.stdout);
+                trace!("{stdout}");
+                if output.stdout.is_empty() { break }
+                let ItemizedChanges { added, updated, deleted } = ItemizedChanges::parse(&stdout);
+                if num_passes == 0 {
+                    debug!("rsync: {added} file(s) added, {updated} updated, {deleted} deleted compared to the previous backup");
+                } else {
+                    debug!("rsync: files changed during the backup, copied again: {added} added, {updated} updated, {deleted} deleted");
+                }
+                num_passes += 1;
+                // saves are off, so whatever keeps changing is probably not part of the world itself, and waiting any longer would keep saves off indefinitely
+                if num_passes >= options.max_rsync_passes {
+                    warn!("files in the {world} world were still changing after {num_passes} rsync passes, continuing anyway");
+                    break
+                }
+            }
+            // partial transfer, e.g. due to a file being locked or deleted by the server
+            Err(Error::Wheel(wheel::Error::CommandExit { output, .. })) if matches!(output.status.code(), Some(23 | 24)) && num_failures < RSYNC_MAX_RETRIES => {
+                debug!("rsync exited with {}, retrying", output.status);
+                sleep(Duration::from_secs(1 << num_failures)).await;
+                num_failures += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    if options.dry_run.is_none() {
+        let temp_path = dir.join(temp_name(&name));
+        let world_dir = options.world_dir(world);
+        let Some(world_dir_name) = world_dir.file_name() else { panic!("world directory at root") };
+        let backed_up_world = temp_path.join(world_dir_name);
+        let has_region_files = fs::exists(backed_up_world.join("region")).await? && pin!(fs::read_dir(backed_up_world.join("region"))).try_next().await?.is_some();
+        if !has_region_files || !fs::exists(backed_up_world.join("level.dat")).await? { return Err(Error::IncompleteWorld(temp_path)) }
+        let path = dir.join(&name);
+        fs::rename(&temp_path, &path).await?;
+        Metadata {
+            minecraft_version: version,
+            server_jar: server_jar(options, world).await?,
+            duration_secs: start.elapsed().as_secs_f64(),
+            hostname: fs::read_to_string("/proc/sys/kernel/hostname").await?.trim().to_owned(),
+            file_count: file_count(&path).await?,
+            uncompressed_size: None,
+        }.write(&path).await?;
+        options.audit("created", world, &path).await?;
+    }
+    Ok(name)
+}
+
+/// How many records `tar` writes between progress reports in `--verbose` mode.
+const TAR_CHECKPOINT_RECORDS: u64 = 1000;
+/// The size of a `tar` record with the default blocking factor of 20.
+const TAR_RECORD_SIZE: u64 = 20 * 512;
+
+/// How much of a backup of the given size has been archived once `tar` has written the given number of records, in percent.
+fn tar_progress(records: u64, size: ByteSize) -> u64 {
+    // headers and padding make the archive slightly larger than the files in it
+    (records * TAR_RECORD_SIZE * 100 / size.as_u64().max(1)).min(100)
+}
+
+/// Runs the given `tar` command that creates the archive with the given name, printing how much of `size` has been archived so far.
+///
+/// The percentage is based on the uncompressed size, so it's accurate regardless of how well the backup compresses.
+async fn tar_with_progress(command: &mut Command, archive_name: &str, size: ByteSize) -> Result<(), Error> {
+    let mut child = command
+        .arg(format!("--checkpoint={TAR_CHECKPOINT_RECORDS}"))
+        .arg("--checkpoint-action=echo=%u")
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+    let mut lines = BufReader::new(child.stderr.take().expect("stderr is piped")).lines();
+    let mut last_percent = None;
+    while let Some(line) = lines.next_line().await? {
+        // checkpoint lines look like `tar: 1000` with the number of records written so far, anything else is an actual message from tar
+        if let Some(records) = line.strip_prefix("tar: ").and_then(|records| records.parse::<u64>().ok()) {
+            let percent = tar_progress(records, size);
+            if last_percent != Some(percent) {
+                debug!("{archive_name}: {percent}%");
+                last_percent = Some(percent);
+            }
+        } else {
+            warn!("{line}");
+        }
+    }
+    let status = child.wait().await?;
+    if status.success() { Ok(()) } else { Err(Error::Tar(status)) }
+}
+
+/// Compresses the given uncompressed backup of the given size, replacing it with the archive.
+async fn compress_one<S: Server>(options: &Options, world: &S, path: PathBuf, size: ByteSize) -> Result<(), Error> {
+    let (Some(extension), Some(tar_flag)) = (options.compression.extension(), options.compress_flag()) else { return Ok(()) };
+    let Some(filename) = path.file_name() else { panic!("backup at root") };
+    let parent = path.parent().unwrap();
+    debug!("compressing {}", filename.to_string_lossy());
+    let archive_name = format!("{}.{extension}", filename.to_str().ok_or(Error::Utf8)?);
+    let mut command = options.command("tar");
+    command
+        .arg(&tar_flag)
+        .arg("-cf")
+        .arg(&archive_name)
+        // entries are otherwise in the file system's directory order, so compressing the same backup again (e.g. after extracting it) could produce a different archive.
+        // With a deterministic compressor like `gzip -n`, it's the same archive. Archives of different backups still differ even if the world didn't change, since members are prefixed with the backup's name.
+        // mtimes and owners are kept, since they're part of the world and needed to restore it faithfully.
+        .arg("--sort=name")
+        .arg(filename)
+        .current_dir(parent);
+    if options.dry_run.is_none() && tracing::enabled!(Level::DEBUG) {
+        options.with_timeout("tar", tar_with_progress(&mut command, &archive_name, size)).await?;
+    } else {
+        options.run(&mut command, "tar").await?;
+    }
+    if options.dry_run.is_some() {
+        options.record_handled(path, ByteSize::default());
+    } else {
+        if options.verify_compressed {
+            // the uncompressed backup is about to be deleted, so make sure the world made it into the archive
+            if !archive_contains(options, &parent.join(&archive_name), &tar_flag, &format!("{}/*/level.dat", filename.to_str().ok_or(Error::Utf8)?)).await? {
+                return Err(Error::IncompleteWorld(parent.join(&archive_name)))
+            }
+        }
+        if options.checksum { write_checksum(&parent.join(&archive_name)).await? }
+        // size is the size of the uncompressed backup, measured before it's deleted below
+        let compressed_size = dir_size(parent.join(&archive_name)).await?;
+        debug!("compressed {} from {size} to {compressed_size} ({:.1}% of the original size)", filename.to_string_lossy(), compressed_size.as_u64() as f64 / size.as_u64().max(1) as f64 * 100.0);
+        options.audit("compressed", world, &parent.join(&archive_name)).await?;
+        if let Some(mut metadata) = Metadata::read(&path).await? {
+            metadata.uncompressed_size = Some(size.as_u64());
+            metadata.write(&path).await?;
+        }
+        for extension in SIDECAR_EXTENSIONS {
+            let sidecar = sidecar_path(&path, extension);
+            if fs::exists(&sidecar).await? {
+                fs::rename(sidecar, sidecar_path(&parent.join(&archive_name), extension)).await?;
+            }
+        }
+        fs::remove_dir_all(path).await?;
+    }
+    Ok(())
+}
+
+/// The uncompressed backups of the given world which may be compressed along with their sizes, smallest first.
+///
+/// The `--keep-uncompressed` newest backups are skipped, as are symlinked backups, since `tar` would only archive the symlink.
+async fn uncompressed_backups<S: Server>(options: &Options, world: &S) -> Result<Vec<(PathBuf, ByteSize)>, Error> {
+    let mut uncompressed = Vec::default();
+    for backup in options.store(world).scan().await?.0.into_iter().rev().skip(options.keep_uncompressed) {
+        if backup.compression == Compression::None && !fs::symlink_metadata(&backup.path).await?.is_symlink() { uncompressed.push((backup.path.clone(), dir_size(&backup.path).await?)) }
+    }
+    uncompressed.sort_by_key(|&(_, size)| size);
+    Ok(uncompressed)
+}
+
+/// Compresses uncompressed backups of the given world, smallest first, running up to `--compress-jobs` instances of `tar` at a time.
+///
+/// Only the given world's backups are considered, so the backups deleted when there's not enough room to compress are from the same world.
+/// The `--keep-uncompressed` newest backups are left uncompressed. In `--incremental` mode, this is at least the newest backup, so it can be used as the `--link-dest` for the next backup.
+pub async fn compress_all<S: Server>(options: &Options, world: &S) -> Result<(), Error> {
+    if options.compression == Compression::None { return Ok(()) }
+    'outer: loop {
+        let uncompressed = uncompressed_backups(options, world).await?;
+        let Some(&(ref path, size)) = uncompressed.first() else { break };
+        while options.available_space()? < size {
+            // not enough room to compress anything, delete backups to make room
+            if !delete_one(options, world).await? { return Err(Error::DiskSpace { required: size, available: options.available_space()? }) }
+            if options.is_handled(path) || !fs::exists(path).await? { continue 'outer }
+        }
+        // start as many jobs as there's room for, assuming each archive may be as large as the backup it replaces
+        let mut reserved = ByteSize::default();
+        let mut batch = Vec::default();
+        let available = options.available_space()?;
+        for (path, size) in uncompressed {
+            if batch.len() >= options.compress_jobs || reserved + size > available { break }
+            reserved += size;
+            batch.push(compress_one(options, world, path, size));
+        }
+        stream::iter(batch).buffer_unordered(options.compress_jobs).try_collect::<Vec<_>>().await?;
+    }
+    Ok(())
+}
+
+/// Backups will be deleted until:
+///
+/// * `amount` can be written while leaving at least `--min-free-bytes` _and_ at least `--min-free-percent` % of the disk free (returns `Ok(true)`),
+/// * only one backup file is remaining (returns `Ok(false)`), or
+/// * an error occurs (returns `Err(_)`).
+///
+/// With `--compress-before-delete`, uncompressed backups are compressed instead as long as there's room to do so.
+///
+/// If any backups were deleted, their number and the space freed are logged afterwards, even without `--verbose`.
+async fn make_room<S: Server>(options: &Options, amount: ByteSize, world: &S) -> Result<bool, Error> {
+    let num_deleted_before = options.deleted.borrow().len();
+    let available_before = options.available_space()?;
+    let has_room = loop {
+        if options.has_room(amount)? { break true }
+        if options.compress_before_delete && options.compression != Compression::None {
+            // tar needs room for the entire archive before the backup it replaces can be deleted, so assume the archive may be as large as the backup
+            let available = options.available_space()?;
+            if let Some((path, size)) = uncompressed_backups(options, world).await?.into_iter().rev().find(|&(_, size)| size <= available) {
+                compress_one(options, world, path, size).await?;
+                continue
+            }
+        }
+        if !delete_one(options, world).await? { break false }
+    };
+    let num_deleted = options.deleted.borrow().len() - num_deleted_before;
+    if num_deleted > 0 {
+        // the individual deletions are only logged with --verbose, so make sure many backups being deleted at once doesn't go unnoticed
+        let freed = ByteSize::b(options.available_space()?.as_u64().saturating_sub(available_before.as_u64()));
+        info!("{} {num_deleted} backup(s) of the {world} world to make room, freeing {freed}", if options.dry_run.is_some() { "would have deleted" } else { "deleted" });
+    }
+    Ok(has_room)
+}
+
+/// Deletes all backups of the given world older than `max_age`, regardless of how close they are to other backups.
+///
+/// Pinned backups and the newest backup are never deleted.
+pub async fn clean<S: Server>(options: &Options, world: &S, max_age: chrono::Duration) -> Result<(), Error> {
+    let cutoff = Utc::now() - max_age;
+    let store = options.store(world);
+    let mut backups = store.list().await?;
+    backups.pop(); // never delete the last remaining backup, even if it's older than max_age
+    for backup in backups {
+        if backup.timestamp < cutoff && !is_pinned(&backup.path).await? { delete_backup(options, &store, backup.filename).await? }
+    }
+    Ok(())
+}
+
+/// Deletes backups using the same heuristic as [`delete_one`] until at most `max_backups` backups remain and they take up at most `max_total_size` in total.
+///
+/// The last remaining backup is never deleted, even if it's larger than `max_total_size`.
+async fn enforce_retention<S: Server>(options: &Options, world: &S, max_backups: Option<usize>, max_total_size: Option<ByteSize>) -> Result<(), Error> {
+    let store = options.store(world);
+    loop {
+        let num_backups = store.list().await?.len();
+        let total_size = if max_total_size.is_some() { store.total_size().await? } else { ByteSize::default() };
+        if max_backups.is_none_or(|max_backups| num_backups <= max_backups) && max_total_size.is_none_or(|max_total_size| total_size <= max_total_size) { break }
+        if !delete_one(options, world).await? { break }
+    }
+    Ok(())
+}
+
+/// Deletes backups within the given time range using the same heuristic as [`delete_one`] until at most `keep` of them remain, or as many as possible if `keep` isn't given.
+///
+/// Backups outside the range are never deleted, but still count as neighbors of the backups within it.
+pub async fn prune<S: Server>(options: &Options, world: &S, keep: Option<usize>, range: TimeRange) -> Result<(), Error> {
+    if keep == Some(0) && range.is_unbounded() { return Err(Error::KeepZero) }
+    let mut num_in_range = 0;
+    for backup in options.store(world).list().await? {
+        if range.contains(backup.timestamp) {
+            num_in_range += 1;
+        } else {
+            options.protected.borrow_mut().insert(backup.timestamp);
+        }
+    }
+    while num_in_range > keep.unwrap_or_default() {
+        if !delete_one(options, world).await? { break }
+        num_in_range -= 1;
+    }
+    Ok(())
+}
+
+/// A backup returned by [`list`], along with the details shown by the `list` subcommand.
+pub struct ListedBackup {
+    pub backup: BackupInfo,
+    pub size: ByteSize,
+    pub pinned: bool,
+    /// The number of files in the backup, or `None` if it was created before this was recorded.
+    pub file_count: Option<u64>,
+    /// The host on which the backup was created, or `None` if it was created before this was recorded.
+    pub hostname: Option<String>,
+}
+
+/// The existing backups of the given world within the given time range, sorted by timestamp.
+///
+/// Entries not matching the filename format are reported as warnings and skipped.
+pub async fn list<S: Server>(store: &BackupStore<'_, S>, range: TimeRange) -> Result<Vec<ListedBackup>, Error> {
+    let (all_backups, invalid) = store.scan().await?;
+    for (filename, e) in invalid {
+        warn!("skipping {filename}: {e}");
+    }
+    let mut backups = Vec::default();
+    for backup in all_backups {
+        if !range.contains(backup.timestamp) { continue }
+        let size = dir_size(&backup.path).await?;
+        // older backups don't have metadata
+        let (file_count, hostname) = Metadata::read(&backup.path).await?.map_or((None, None), |metadata| (Some(metadata.file_count), Some(metadata.hostname)));
+        let pinned = is_pinned(&backup.path).await?;
+        backups.push(ListedBackup { backup, size, pinned, file_count, hostname });
+    }
+    // sorting by filename would separate backups with the same version if a longer version sorts between them
+    backups.sort_by(|listed1, listed2| listed1.backup.timestamp.cmp(&listed2.backup.timestamp).then_with(|| listed1.backup.version.cmp(&listed2.backup.version)));
+    for (listed, _) in backups.iter().tuple_windows().filter(|(listed1, listed2)| listed1.backup.timestamp == listed2.backup.timestamp && listed1.backup.version == listed2.backup.version).unique_by(|(listed, _)| (listed.backup.timestamp, listed.backup.version.clone())) {
+        warn!("multiple backups with timestamp {} and version {}", store.timestamp_format.display(listed.backup.timestamp), listed.backup.version);
+    }
+    Ok(backups)
+}
+
+/// An overview of the existing backups of a world, shown by the `stats` subcommand.
+#[derive(Serialize)]
+pub struct Stats {
+    pub world: String,
+    pub num_backups: usize,
+    pub num_compressed: usize,
+    pub num_uncompressed: usize,
+    /// The total size of the backups in bytes, not including sidecar files.
+    pub total_size: u64,
+    /// The average size of a backup in bytes, or `None` if there are no backups.
+    pub average_size: Option<u64>,
+    /// The timestamp of the oldest backup, in the configured timestamp format.
+    pub oldest: Option<String>,
+    /// The timestamp of the newest backup, in the configured timestamp format.
+    pub newest: Option<String>,
+    /// The Minecraft versions of the backups, in the order in which they were first backed up.
+    pub versions: Vec<String>,
+}
+
+/// Summarizes the existing backups of the given world.
+///
+/// Entries not matching the filename format are reported as warnings and skipped.
+pub async fn stats<S: Server>(store: &BackupStore<'_, S>) -> Result<Stats, Error> {
+    let (all_backups, invalid) = store.scan().await?;
+    for (filename, e) in invalid {
+        warn!("skipping {filename}: {e}");
+    }
+    let mut backups = Vec::default();
+    for BackupInfo { path, timestamp, version, compression, .. } in all_backups {
+        backups.push((timestamp, version, compression, dir_size(&path).await?));
+    }
+    let total_size = backups.iter().fold(ByteSize::default(), |total, &(_, _, _, size)| total + size);
+    let num_compressed = backups.iter().filter(|&&(_, _, compression, _)| compression != Compression::None).count();
+    Ok(Stats {
+        world: store.world.to_string(),
+        num_backups: backups.len(),
+        num_uncompressed: backups.len() - num_compressed,
+        total_size: total_size.as_u64(),
+        average_size: (!backups.is_empty()).then(|| total_size.as_u64() / backups.len() as u64),
+        oldest: backups.first().map(|&(timestamp, ..)| store.timestamp_format.display(timestamp).to_string()),
+        newest: backups.last().map(|&(timestamp, ..)| store.timestamp_format.display(timestamp).to_string()),
+        versions: backups.into_iter().map(|(_, version, _, _)| version).unique().collect(),
+        num_compressed,
+    })
+}
+
+/// Fails with [`Error::Downgrade`] if a backup of the given Minecraft version is older than the version of the world's server.
+///
+/// Snapshots can't be compared to releases, so they're never considered older.
+pub async fn check_downgrade<S: Server>(options: &Options, world: &S, backup_version: &str) -> Result<(), Error> {
+    let live_version = world_version(options, world).await?;
+    if let (Version::Release(backup), Version::Release(live)) = (Version::parse(backup_version), Version::parse(&live_version)) {
+        if backup < live {
+            warn!("the backup is of Minecraft {backup_version}, but the {world} server runs {live_version}");
+            return Err(Error::Downgrade { backup: backup_version.to_owned(), live: live_version })
+        }
+    }
+    Ok(())
+}
+
+/// Restores the given backup of the given world into `target`, removing anything not present in the backup.
+///
+/// `target` is usually the world directory, but can be any absolute path whose parent exists.
+/// Compressed backups are first extracted into a temporary directory next to `target`, and the world folder nested inside is moved into place if `target` doesn't exist yet.
+/// Otherwise, the world folder nested inside the backup is synced into `target` itself.
+///
+/// If `only` is nonempty, only the paths in the world matching any of these patterns are restored (along with their contents, if they're directories),
+/// nothing else in `target` is removed, and existing files are only overwritten if `overwrite` is `true`. The restored paths are logged.
+///
+/// Note that a running server may still write chunks it has loaded back to disk after the restore, so it's safest to stop the server first.
+pub async fn restore<S: Server>(options: &Options, world: &S, target: &Path, path: &Path, compression: Compression, only: &[String], overwrite: bool) -> Result<(), Error> {
+    let world_dir = options.world_dir(world);
+    // the backup contains a folder named after the world directory, regardless of where it's restored to
+    let Some(world_dir_name) = world_dir.file_name() else { panic!("world directory at root") };
+    let Some(target_parent) = target.parent() else { panic!("restore target at root") };
+    info!("restoring {}", path.file_name().unwrap_or_default().to_string_lossy());
+    if let Some(metadata) = Metadata::read(path).await? {
+        info!("restoring backup of Minecraft {} with {} files, created on {}", metadata.minecraft_version, metadata.file_count, metadata.hostname);
+    }
+    let world_dir_name = world_dir_name.to_str().ok_or(Error::Utf8)?;
+    let extracted = if let (Some(extension), Some(tar_flag)) = (compression.extension(), compression.tar_flag()) {
+        debug!("extracting {}", path.display());
+        let Some(filename) = path.file_name() else { panic!("backup at root") };
+        let backup_name = filename.to_str().ok_or(Error::Utf8)?.strip_suffix(&format!(".{extension}")).ok_or(Error::FilenameFormat)?;
+        // extracted under a temporary name, so a failed extraction is never mistaken for the backup or the restored world
+        let tmp = target_parent.join(temp_name(backup_name));
+        if options.dry_run.is_none() { fs::create_dir_all(&tmp).await?; }
+        let mut command = options.command("tar");
+        command
+            .arg(tar_flag)
+            .arg("-xf")
+            .arg(path)
+            .current_dir(&tmp);
+        if !only.is_empty() {
+            // only extract the matching members, which are nested inside the backup folder and the world folder
+            command.arg("--wildcards").arg("--no-wildcards-match-slash");
+            command.args(only.iter().map(|pattern| format!("{backup_name}/{world_dir_name}/{}", pattern.trim_start_matches('/'))));
+        }
+        if let Err(e) = options.run(&mut command, "tar").await {
+            if options.dry_run.is_none() { fs::remove_dir_all(&tmp).await?; }
+            return Err(e)
+        }
+        if only.is_empty() && options.dry_run.is_none() && !fs::exists(target).await? {
+            // nothing to sync into, so the extracted world folder can be moved into place
+            debug!("moving the extracted world into {}", target.display());
+            fs::rename(tmp.join(backup_name).join(world_dir_name), target).await?;
+            fs::remove_dir_all(&tmp).await?;
+            return Ok(())
+        }
+        Some((tmp, backup_name))
+    } else {
+        None
+    };
+    let mut source = extracted.as_ref().map_or_else(|| path.to_owned(), |(tmp, backup_name)| tmp.join(backup_name)).join(world_dir_name).into_os_string();
+    source.push("/"); // sync the contents of the nested world folder rather than the folder itself
+    debug!("restoring {} into {}", path.display(), target.display());
+    let mut command = options.rsync();
+    command.arg("--archive");
+    if only.is_empty() {
+        command.arg("--delete");
+    } else {
+        command.arg("--prune-empty-dirs").arg("--out-format=%n");
+        if !overwrite { command.arg("--ignore-existing"); }
+        for pattern in only {
+            // anchor the pattern at the world folder and include the contents of matching directories
+            let pattern = pattern.trim_start_matches('/');
+            command.arg("--include").arg(format!("/{pattern}")).arg("--include").arg(format!("/{pattern}/***"));
+        }
+        command.arg("--include").arg("*/").arg("--exclude").arg("*");
+    }
+    let res = options.run(command.arg(source).arg(target), "rsync").await;
+    if let Ok(Some(ref output)) = res {
+        if !only.is_empty() {
+            for line in String::from_utf8_lossy(&output.stdout).lines() {
+                if !line.ends_with('/') { info!("restored {line}") }
+            }
+        }
+    }
+    if let Some((tmp, _)) = extracted {
+        if options.dry_run.is_none() {
+            fs::remove_dir_all(tmp).await?;
+        }
+    }
+    res?;
+    Ok(())
+}
+
+/// Adds the given archive to the backups of the given world as if it had been created at `timestamp` for Minecraft `version`, so it's treated like any other backup.
+///
+/// The archive must be compressed using gzip or zstd and contain a world folder with a `level.dat`. If the world folder isn't nested like in the archives created by wurstminebackup,
+/// the archive is extracted into a temporary directory in the backup path and archived again with the expected layout.
+pub async fn import<S: Server>(options: &Options, world: &S, archive: &Path, timestamp: DateTime<Utc>, version: &str) -> Result<(), Error> {
+    let archive_name = archive.file_name().and_then(OsStr::to_str).ok_or(Error::Utf8)?;
+    let compression = [Compression::Gzip, Compression::Zstd].into_iter()
+        .find(|compression| compression.extension().is_some_and(|extension| archive_name.ends_with(&format!(".{extension}"))))
+        .ok_or_else(|| Error::ImportFormat(archive.to_owned()))?;
+    let (Some(extension), Some(tar_flag)) = (compression.extension(), compression.tar_flag()) else { unreachable!("compressed archive") };
+    let dir = options.store(world).dir();
+    if fs::exists(&dir).await? {
+        match options.store(world).find(timestamp).await {
+            Ok(_) | Err(Error::AmbiguousTimestamp(_)) => return Err(Error::DuplicateTimestamp(options.timestamp_format.display(timestamp).to_string())),
+            Err(Error::NoSuchBackup(_)) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    let name = format!("{}_{version}", options.timestamp_format.display(timestamp));
+    let filename = format!("{name}.{extension}");
+    // make sure the rest of the tooling will recognize the backup
+    if options.timestamp_format.parse_filename(&filename).ok().is_none_or(|(parsed_timestamp, parsed_version, _)| parsed_timestamp != timestamp || parsed_version != version) { return Err(Error::FilenameFormat) }
+    let world_dir = options.world_dir(world);
+    let Some(world_dir_name) = world_dir.file_name() else { panic!("world directory at root") };
+    let world_dir_name = world_dir_name.to_str().ok_or(Error::Utf8)?;
+    let members = options.command("tar").arg(tar_flag).arg("-tf").arg(archive).check("tar").await?.stdout;
+    let members = String::from_utf8(members).map_err(|_| Error::Utf8)?;
+    // the world folder is the one containing the shallowest level.dat
+    let Some(world_prefix) = members.lines()
+        .filter_map(|member| member.trim_start_matches("./").strip_suffix("level.dat"))
+        .filter(|prefix| prefix.is_empty() || prefix.ends_with('/'))
+        .min_by_key(|prefix| prefix.matches('/').count())
+    else { return Err(Error::IncompleteWorld(archive.to_owned())) };
+    let path = dir.join(&filename);
+    if options.dry_run.is_some() {
+        info!("would import {} as {}", archive.display(), path.display());
+        return Ok(())
+    }
+    fs::create_dir_all(&dir).await?;
+    // like in make_backup, the archive only gets its real name once it's complete
+    let temp_path = dir.join(temp_name(&filename));
+    if world_prefix == format!("{name}/{world_dir_name}/") {
+        debug!("copying {} to {}", archive.display(), path.display());
+        tokio::fs::copy(archive, &temp_path).await.at(&temp_path)?; //TODO wheel
+    } else {
+        debug!("rearranging {} into {}", archive.display(), path.display());
+        // next to the backups under a temporary name, so it's cleaned up by remove_temporaries if this is interrupted
+        let tmp = dir.join(temp_name(&name));
+        let extracted = tmp.join("extracted");
+        fs::create_dir_all(&extracted).await?;
+        options.run(options.command("tar").arg(tar_flag).arg("-xf").arg(archive).arg("-C").arg(&extracted), "tar").await?;
+        fs::create_dir_all(tmp.join(&name)).await?;
+        fs::rename(if world_prefix.is_empty() { extracted } else { extracted.join(world_prefix) }, tmp.join(&name).join(world_dir_name)).await?;
+        options.run(options.command("tar").arg(tar_flag).arg("-cf").arg(&temp_path).arg("--sort=name").arg("-C").arg(&tmp).arg(&name), "tar").await?;
+        fs::remove_dir_all(tmp).await?;
+    }
+    fs::rename(&temp_path, &path).await?;
+    options.audit("imported", world, &path).await?;
+    info!("imported {} as {filename}", archive.display());
+    Ok(())
+}
+
+/// Writes the given backup to `output` as a single archive with the same layout as the archives in the backup path, so it can be handed to someone else.
+///
+/// The extension of `output` (`.tar.gz`, `.tar.zst`, or `.tar`) selects the compression method. Compressed backups are copied as they are, so they can only be exported with their own extension.
+/// Uncompressed backups are archived using `--compression` and its compressor settings if it matches the extension, or using `tar`'s default for the extension otherwise.
+/// If `checksum` is true, the archive's SHA-256 checksum is returned, except in `--dry-run` mode.
+pub async fn export(options: &Options, path: &Path, compression: Compression, output: &Path, checksum: bool) -> Result<Option<String>, Error> {
+    let output_name = output.file_name().and_then(OsStr::to_str).ok_or(Error::Utf8)?;
+    let output_compression = [Compression::Gzip, Compression::Zstd, Compression::None].into_iter()
+        .find(|output_compression| output_name.ends_with(&format!(".{}", output_compression.extension().unwrap_or("tar"))))
+        .ok_or_else(|| Error::ExportFormat(output.to_owned()))?;
+    if compression == Compression::None {
+        let Some(filename) = path.file_name() else { panic!("backup at root") };
+        // the configured compressor is only used if it matches the extension of the output
+        let tar_flag = if output_compression == options.compression { options.compress_flag() } else { output_compression.tar_flag().map(str::to_owned) };
+        debug!("archiving {} to {}", path.display(), output.display());
+        let mut tar = options.command("tar");
+        if let Some(tar_flag) = tar_flag { tar.arg(tar_flag); }
+        options.run(tar
+            .arg("-cf")
+            .arg(output)
+            .arg("--sort=name")
+            .arg("-C")
+            .arg(path.parent().unwrap())
+            .arg(filename), "tar").await?;
+    } else if compression != output_compression {
+        return Err(Error::ExportCompression { compression })
+    } else if options.dry_run.is_some() {
+        info!("would copy {} to {}", path.display(), output.display());
+    } else {
+        debug!("copying {} to {}", path.display(), output.display());
+        tokio::fs::copy(path, output).await.at(output)?; //TODO wheel
+    }
+    if options.dry_run.is_some() { return Ok(None) }
+    info!("exported to {} ({})", output.display(), dir_size(output).await?);
+    Ok(if checksum { Some(sha256(output).await?) } else { None })
+}
+
+/// The regular files in a backup, keyed by their path relative to the backup, as their size in bytes and their modification time in seconds since the Unix epoch.
+type FileListing = BTreeMap<PathBuf, (u64, i64)>;
+
+/// Lists the regular files in the given directory and anything inside it. `relative_path` is the path of `path` relative to the backup.
+fn dir_listing(path: impl AsRef<Path>, relative_path: PathBuf) -> Pin<Box<dyn Future<Output = wheel::Result<FileListing>>>> {
+    let path = path.as_ref().to_owned();
+    Box::pin(async move {
+        let metadata = fs::symlink_metadata(&path).await?;
+        if metadata.is_dir() {
+            fs::read_dir(path)
+                .map_ok(|entry| dir_listing(entry.path(), relative_path.join(entry.file_name())))
+                .try_buffer_unordered(DIR_SIZE_CONCURRENCY)
+                .try_fold(FileListing::default(), |mut listing, entry_listing| async move {
+                    listing.extend(entry_listing);
+                    Ok(listing)
+                })
+                .await
+        } else if metadata.is_file() {
+            let modified = metadata.modified().at(&path)?;
+            Ok(FileListing::from([(relative_path, (metadata.len(), DateTime::<Utc>::from(modified).timestamp()))]))
+        } else {
+            Ok(FileListing::default())
+        }
+    })
+}
+
+/// Lists the regular files in the given backup. Compressed backups are listed using `tar` without extracting them.
+async fn file_listing(path: &Path, compression: Compression) -> Result<FileListing, Error> {
+    let Some(tar_flag) = compression.tar_flag() else { return Ok(dir_listing(path, PathBuf::default()).await?) };
+    let output = Command::new("tar")
+        .arg(tar_flag)
+        .arg("-tvf")
+        .arg(path)
+        .arg("--full-time")
+        .env("TZ", "UTC") // so the modification times can be compared with those of uncompressed backups
+        .check("tar").await?;
+    let mut listing = FileListing::default();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        // lines look like `-rw-r--r-- minecraft/minecraft 12345 2024-01-01 12:00:00 2024-01-01_12-00-00_1.20.4/wurstmineberg/level.dat`
+        let Some((_, size, modified, name)) = regex_captures!(r"^-\S*\s+\S+\s+([0-9]+)\s+([0-9]{4}-[0-9]{2}-[0-9]{2} [0-9]{2}:[0-9]{2}:[0-9]{2})\s(.*)$", line) else { continue };
+        // paths in the archive start with the backup folder
+        let relative_path = Path::new(name).components().skip(1).collect();
+        listing.insert(relative_path, (size.parse().expect("matched by regex"), NaiveDateTime::parse_from_str(modified, "%Y-%m-%d %H:%M:%S")?.and_utc().timestamp()));
+    }
+    Ok(listing)
+}
+
+/// A checksum of the contents of the given compressed backup which doesn't depend on the backup's name, unlike the archive's own checksum, since the archive's members are prefixed with the name.
+///
+/// This covers the metadata listed by `tar -tv` (type, permissions, owner, size, modification time, and link targets) of everything except the top-level directory, and the contents of all files in archive order.
+async fn content_checksum(options: &Options, backup: &BackupInfo) -> Result<String, Error> {
+    let (Some(extension), Some(tar_flag)) = (backup.compression.extension(), backup.compression.tar_flag()) else { panic!("content_checksum called on uncompressed backup") };
+    let Some(name) = backup.filename.strip_suffix(&format!(".{extension}")) else { panic!("content_checksum called on backup with wrong extension") };
+    let mut hasher = Sha256::default();
+    let listing = options.command("tar")
+        .arg(tar_flag)
+        .arg("-tvf")
+        .arg(&backup.path)
+        .arg("--full-time")
+        .env("TZ", "UTC") // so the checksum doesn't depend on the local time zone
+        .check("tar").await?
+        .stdout;
+    for line in String::from_utf8(listing).map_err(|_| Error::Utf8)?.lines() {
+        // the top-level directory is the backup folder itself, whose modification time is when the backup was created
+        if line.ends_with(&format!(" {name}/")) { continue }
+        // prefixes both the member's path and the target of a hardlink
+        hasher.update(line.replace(&format!(" {name}/"), " ").as_bytes());
+        hasher.update(b"\n");
+    }
+    let mut child = options.command("tar")
+        .arg(tar_flag)
+        .arg("-xOf")
+        .arg(&backup.path)
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let mut buf = vec![0; 1024 * 1024];
+    loop {
+        let len = stdout.read(&mut buf).await?;
+        if len == 0 { break }
+        hasher.update(&buf[..len]);
+    }
+    let status = child.wait().await?;
+    if !status.success() { return Err(Error::Tar(status)) }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Marks each compressed backup of the given world with the same contents as the previous backup by writing the previous backup's filename to a `.duplicate` file next to it, or only simulates this in `--dry-run` mode.
+/// Logs how much space the duplicates take up. Duplicates are deleted before any other backups when making room (see [`BackupStore::delete_closest`]).
+///
+/// Archives of different backups never have the same checksum since their members are prefixed with the backup's name, so they're compared using [`content_checksum`].
+/// Symlinked backups are skipped, since deleting them wouldn't free any space.
+pub async fn dedup<S: Server>(options: &Options, world: &S) -> Result<(), Error> {
+    let mut num_duplicates = 0;
+    let mut duplicate_size = ByteSize::default();
+    let backups = options.store(world).list().await?;
+    let mut prev = None::<(&BackupInfo, String)>;
+    for backup in &backups {
+        let checksum = if backup.compression != Compression::None && fs::symlink_metadata(&backup.path).await?.is_file() {
+            Some(content_checksum(options, backup).await?)
+        } else {
+            None
+        };
+        if let (Some((prev, prev_checksum)), Some(checksum)) = (&prev, &checksum) {
+            if prev.compression == backup.compression && prev_checksum == checksum {
+                if duplicate_of(&backup.path).await?.as_ref() != Some(&prev.filename) {
+                    if options.dry_run.is_some() {
+                        info!("would mark {} as a duplicate of {}", backup.filename, prev.filename);
+                    } else {
+                        debug!("marking {} as a duplicate of {}", backup.filename, prev.filename);
+                        fs::write(sidecar_path(&backup.path, "duplicate"), &prev.filename).await?;
+                    }
+                }
+                num_duplicates += 1;
+                duplicate_size += dir_size(&backup.path).await?;
+            }
+        }
+        prev = checksum.map(|checksum| (backup, checksum));
+    }
+    if num_duplicates > 0 {
+        info!("{num_duplicates} backup(s) of the {world} world taking up {duplicate_size} {} duplicates of the previous backup and will be deleted before any other backups when making room", if options.dry_run.is_some() { "would be marked as" } else { "are" });
+    } else {
+        info!("no duplicate backups of the {world} world");
+    }
+    Ok(())
+}
+
+/// How a file differs between two backups.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Added,
+    Deleted,
+    Modified,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Added => write!(f, "A"),
+            Self::Deleted => write!(f, "D"),
+            Self::Modified => write!(f, "M"),
+        }
+    }
+}
+
+/// The files which were added, deleted, or modified between the given backups of the given world, sorted by path.
+///
+/// Files are considered modified if their size or modification time differs.
+pub async fn diff<S: Server>(store: &BackupStore<'_, S>, old: DateTime<Utc>, new: DateTime<Utc>) -> Result<Vec<(Change, PathBuf)>, Error> {
+    let old = store.find(old).await?;
+    let new = store.find(new).await?;
+    let old = file_listing(&old.path, old.compression).await?;
+    let new = file_listing(&new.path, new.compression).await?;
+    Ok(old.into_iter().merge_join_by(new, |(old_path, _), (new_path, _)| old_path.cmp(new_path)).filter_map(|entry| match entry {
+        EitherOrBoth::Left((path, _)) => Some((Change::Deleted, path)),
+        EitherOrBoth::Right((path, _)) => Some((Change::Added, path)),
+        EitherOrBoth::Both((path, old), (_, new)) => (old != new).then_some((Change::Modified, path)),
+    }).collect())
+}
+
+/// The result of a health check, ordered by severity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CheckStatus {
+    Ok,
+    Warn,
+    Crit,
+}
+
+impl CheckStatus {
+    /// The exit status used by Nagios plugins for this result. Errors while checking use 3 (unknown).
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Ok => 0,
+            Self::Warn => 1,
+            Self::Crit => 2,
+        }
+    }
+}
+
+impl fmt::Display for CheckStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ok => write!(f, "OK"),
+            Self::Warn => write!(f, "WARN"),
+            Self::Crit => write!(f, "CRIT"),
+        }
+    }
+}
+
+/// Checks that the backup drive is mounted and has `--min-free-bytes` and `--min-free-percent` free, and that the newest backup of the given world
+/// is younger than `max_age` (or twice that for a critical result) and contains a `level.dat`. Returns the overall result and a description of each check.
+pub async fn health_check<S: Server>(options: &Options, world: &S, max_age: Duration, allow_unmounted: bool) -> Result<(CheckStatus, Vec<String>), Error> {
+    let mut status = CheckStatus::Ok;
+    let mut messages = Vec::default();
+    if !allow_unmounted {
+        if let Err(e @ (Error::NoMount | Error::Unmounted(_))) = options.check_mounted() { return Ok((CheckStatus::Crit, vec![e.to_string()])) }
+    }
+    let available = options.available_space()?;
+    if options.has_room(ByteSize::default())? {
+        messages.push(format!("{available} free"));
+    } else {
+        status = status.max(CheckStatus::Warn);
+        messages.push(format!("only {available} free"));
+    }
+    let Some(newest) = options.store(world).newest().await? else {
+        messages.insert(0, format!("no backups of the {world} world"));
+        return Ok((CheckStatus::Crit, messages))
+    };
+    let age = (Utc::now() - newest.timestamp).to_std().unwrap_or_default();
+    let age_status = if age > max_age * 2 { CheckStatus::Crit } else if age > max_age { CheckStatus::Warn } else { CheckStatus::Ok };
+    status = status.max(age_status);
+    messages.insert(0, format!("newest backup {} is {}h old", options.timestamp_format.display(newest.timestamp), age.as_secs() / 3600));
+    let readable = if let Some(tar_flag) = newest.compression.tar_flag() {
+        // listing the archive decompresses it in full, so this also catches truncated archives
+        archive_contains(options, &newest.path, tar_flag, "*/*/level.dat").await?
+    } else {
+        let world_dir = options.world_dir(world);
+        let Some(world_dir_name) = world_dir.file_name() else { panic!("world directory at root") };
+        fs::exists(newest.path.join(world_dir_name).join("level.dat")).await?
+    };
+    if !readable {
+        status = CheckStatus::Crit;
+        messages.insert(1, String::from("newest backup is corrupt or missing its level.dat"));
+    }
+    Ok((status, messages))
+}
+
+/// Checks that the given backup can be read in full.
+///
+/// Compressed backups are listed using `tar`, which decompresses the entire archive, and compared against their `.sha256` file if there is one. For uncompressed backups, the metadata of every file is read.
+async fn verify_backup(options: &Options, path: &Path, compression: Compression) -> Result<(), Error> {
+    if let Some(tar_flag) = compression.tar_flag() {
+        options.command("tar")
+            .arg(tar_flag)
+            .arg("-tf")
+            .arg(path)
+            .stdout(Stdio::null())
+            .check("tar").await?;
+        let checksum_path = sidecar_path(path, "sha256");
+        if fs::exists(&checksum_path).await? {
+            let expected = fs::read_to_string(&checksum_path).await?;
+            if expected.split_whitespace().next() != Some(&*sha256(path).await?) { return Err(Error::ChecksumMismatch(path.to_owned())) }
+        } else {
+            warn!("no checksum file for {}", path.display());
+        }
+    } else {
+        dir_size(path).await?;
+    }
+    Ok(())
+}
+
+/// Checks the integrity of all backups of the given world, or only the one with the given timestamp.
+///
+/// Errors are reported for each backup that fails verification, and an error is returned at the end if any did.
+pub async fn verify<S: Server>(options: &Options, world: &S, timestamp: Option<DateTime<Utc>>) -> Result<(), Error> {
+    let backups = if let Some(timestamp) = timestamp {
+        let backup = options.store(world).find(timestamp).await?;
+        vec![(backup.path, backup.compression)]
+    } else {
+        let (backups, invalid) = options.store(world).scan().await?;
+        for (filename, e) in invalid {
+            warn!("skipping {filename}: {e}");
+        }
+        backups.into_iter().map(|backup| (backup.path, backup.compression)).collect()
+    };
+    let mut num_corrupt = 0;
+    for (path, compression) in backups {
+        debug!("verifying {}", path.display());
+        if let Err(e) = verify_backup(options, &path, compression).await {
+            error!("{}: {e}", path.display());
+            num_corrupt += 1;
+        }
+    }
+    if num_corrupt > 0 { Err(Error::Corrupt(num_corrupt)) } else { Ok(()) }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+/// A summary of a backup run, printed as JSON in `--format json` mode and as a single line otherwise.
+#[derive(Serialize)]
+pub struct Summary {
+    pub world: String,
+    /// The filename of the created backup, or `None` if the backup was skipped.
+    pub backup: Option<String>,
+    /// Why the backup was skipped, or `None` if a backup was created.
+    pub skipped: Option<SkipReason>,
+    /// The full path of the created backup, or its rsync destination if it's on a remote host.
+    pub path: Option<String>,
+    /// The Minecraft version of the created backup.
+    pub version: Option<String>,
+    /// The size of the created backup in bytes, or `None` if the backup was skipped or is on a remote host.
+    pub size: Option<u64>,
+    /// The filenames of the backups deleted during this run.
+    pub deleted: Vec<String>,
+    /// The space available on the backup mount at the end of the run in bytes, or `None` if the backup path is on a remote host.
+    pub free_space: Option<u64>,
+}
+
+impl Summary {
+    fn skipped<S: Server>(options: &Options, world: &S, reason: SkipReason) -> Result<Self, Error> {
+        Ok(Self {
+            world: world.to_string(),
+            backup: None,
+            skipped: Some(reason),
+            path: None,
+            version: None,
+            size: None,
+            deleted: Vec::default(),
+            free_space: Some(options.available_space()?.as_u64()),
+        })
+    }
+}
+
+/// Why a backup run didn't create a backup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    /// The world hasn't changed since the newest backup.
+    Unchanged,
+    /// The newest backup is younger than `--min-interval`.
+    MinInterval,
+}
+
+impl SkipReason {
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::Unchanged => "the world hasn't changed since the last backup",
+            Self::MinInterval => "the newest backup is younger than --min-interval",
+        }
+    }
+}
+
+/// Writes metrics about the backups of the given worlds to the given file, in the format read by the textfile collector of the Prometheus node exporter.
+///
+/// The file is written under a temporary name and then renamed, so the collector never reads a partially written file. Nothing is written in `--dry-run` mode.
+pub async fn write_metrics<S: Server>(options: &Options, worlds: &[S], path: &Path) -> Result<(), Error> {
+    if options.dry_run.is_some() {
+        info!("would write metrics to {}", path.display());
+        return Ok(())
+    }
+    let mut last_success = String::default();
+    let mut backup_size = String::default();
+    let mut backups_total = String::default();
+    let mut free_bytes = String::default();
+    let available = options.available_space()?.as_u64();
+    for world in worlds {
+        let labels = format!("{{world=\"{}\"}}", world.to_string().replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n"));
+        let backups = options.store(world).scan().await?.0;
+        if let Some(newest) = backups.last() {
+            last_success.push_str(&format!("wurstminebackup_last_success_timestamp{labels} {}\n", newest.timestamp.timestamp()));
+            backup_size.push_str(&format!("wurstminebackup_backup_size_bytes{labels} {}\n", dir_size(&newest.path).await?.as_u64()));
+        }
+        backups_total.push_str(&format!("wurstminebackup_backups_total{labels} {}\n", backups.len()));
+        // repeated for each world so it can be matched with the other metrics by its labels
+        free_bytes.push_str(&format!("wurstminebackup_free_bytes{labels} {available}\n"));
+    }
+    let metrics = format!("\
+        # HELP wurstminebackup_last_success_timestamp The time at which the newest backup of the world was taken, in seconds since the Unix epoch.\n\
+        # TYPE wurstminebackup_last_success_timestamp gauge\n\
+        {last_success}\
+        # HELP wurstminebackup_backup_size_bytes The size of the newest backup of the world.\n\
+        # TYPE wurstminebackup_backup_size_bytes gauge\n\
+        {backup_size}\
+        # HELP wurstminebackup_backups_total The number of backups of the world.\n\
+        # TYPE wurstminebackup_backups_total gauge\n\
+        {backups_total}\
+        # HELP wurstminebackup_free_bytes The space available on the backup drive.\n\
+        # TYPE wurstminebackup_free_bytes gauge\n\
+        {free_bytes}\
+    ");
+    let Some(filename) = path.file_name() else { panic!("metrics file at root") };
+    let temp_path = path.with_file_name(temp_name(filename.to_str().ok_or(Error::Utf8)?));
+    fs::write(&temp_path, metrics).await?;
+    fs::rename(temp_path, path).await?;
+    Ok(())
+}
+
+/// The environment variable which may contain the Discord webhook URL.
+pub const DISCORD_WEBHOOK_ENV: &str = "WURSTMINEBACKUP_DISCORD_WEBHOOK";
+/// The name of the systemd credential (see `LoadCredential=` in `systemd.exec(5)`) which may contain the Discord webhook URL.
+pub const DISCORD_WEBHOOK_CREDENTIAL: &str = "discord-webhook";
+
+/// Reads a secret from the given environment variable, or failing that, from the given systemd credential.
+pub async fn secret(env_var: &str, credential: &str) -> Result<Option<String>, Error> {
+    if let Some(value) = env::var_os(env_var).filter(|value| !value.is_empty()) { return Ok(Some(value.into_string()?)) }
+    if let Some(credentials_dir) = env::var_os("CREDENTIALS_DIRECTORY") {
+        let path = Path::new(&credentials_dir).join(credential);
+        if fs::exists(&path).await? { return Ok(Some(fs::read_to_string(path).await?.trim().to_owned())) }
+    }
+    Ok(None)
+}
+
+/// Posts the result of a backup run to the given Discord webhook.
+async fn notify_discord<S: Server>(options: &Options, webhook: &str, world: &S, res: &Result<Summary, Error>) -> Result<(), reqwest::Error> {
+    let free_space_field = |free_space: ByteSize| json!({"name": "free space", "value": free_space.to_string(), "inline": true});
+    let embed = match res {
+        Ok(Summary { backup: Some(backup), size, free_space, .. }) => json!({
+            "title": format!("{world} backup succeeded"),
+            "color": 0x00aa00,
+            "fields": iter::once(json!({"name": "backup", "value": backup}))
+                .chain(size.map(|size| json!({"name": "size", "value": ByteSize::b(size).to_string(), "inline": true})))
+                .chain(free_space.map(|free_space| free_space_field(ByteSize::b(free_space))))
+                .collect_vec(),
+        }),
+        Ok(Summary { skipped, free_space, .. }) => json!({
+            "title": format!("{world} backup skipped"),
+            "color": 0x00aa00,
+            "description": skipped.map(|reason| format!("Skipped because {}.", reason.description())),
+            "fields": free_space.map(|free_space| free_space_field(ByteSize::b(free_space))).into_iter().collect_vec(),
+        }),
+        Err(e) => json!({
+            "title": format!("{world} backup failed"),
+            "color": 0xaa0000,
+            "description": e.to_string(),
+            "fields": options.remote.is_none().then(|| options.available_space().ok()).flatten().map(free_space_field).into_iter().collect_vec(),
+        }),
+    };
+    reqwest::Client::new().post(webhook)
+        .json(&json!({"embeds": [embed]}))
+        .send().await?
+        .error_for_status()?;
+    Ok(())
+}
+
+/// Where and when to send a report of each backup by email.
+pub struct EmailReport {
+    pub to: String,
+    /// If not given, `sendmail` picks a sender address, usually based on the current user.
+    pub from: Option<String>,
+    /// A program compatible with `sendmail -t`, which is responsible for delivering the email, e.g. via SMTP.
+    pub sendmail: PathBuf,
+    /// Whether to send a report only if the backup failed.
+    pub only_on_failure: bool,
+}
+
+/// Sends a report of the result of a backup run by email using `sendmail`.
+async fn notify_email<S: Server>(options: &Options, email: &EmailReport, world: &S, res: &Result<Summary, Error>) -> Result<(), Error> {
+    if email.only_on_failure && res.is_ok() { return Ok(()) }
+    let mut lines = Vec::default();
+    let subject = match res {
+        Ok(Summary { backup, skipped, size, deleted, free_space, .. }) => {
+            if let Some(backup) = backup {
+                lines.push(format!("backup: {backup}"));
+            } else if let Some(reason) = skipped {
+                lines.push(format!("Skipped because {}.", reason.description()));
+            }
+            if let Some(size) = size { lines.push(format!("size: {}", ByteSize::b(*size))) }
+            if !deleted.is_empty() { lines.push(format!("deleted: {}", deleted.join(", "))) }
+            if let Some(free_space) = free_space { lines.push(format!("free space: {}", ByteSize::b(*free_space))) }
+            format!("{world} backup {}", if backup.is_some() { "succeeded" } else { "skipped" })
+        }
+        Err(e) => {
+            lines.push(e.to_string());
+            if let Some(free_space) = options.remote.is_none().then(|| options.available_space().ok()).flatten() { lines.push(format!("free space: {free_space}")) }
+            format!("{world} backup failed")
+        }
+    };
+    let mut message = String::default();
+    if let Some(ref from) = email.from { message.push_str(&format!("From: {from}\n")) }
+    message.push_str(&format!("To: {}\nSubject: {subject}\nContent-Type: text/plain; charset=utf-8\n\n{}\n", email.to, lines.join("\n")));
+    let mut sendmail = Command::new(&email.sendmail).arg("-t").stdin(Stdio::piped()).spawn().at(&email.sendmail)?;
+    let mut stdin = sendmail.stdin.take().expect("sendmail stdin is piped");
+    stdin.write_all(message.as_bytes()).await.at(&email.sendmail)?;
+    drop(stdin); // sendmail reads until EOF
+    let status = sendmail.wait().await.at(&email.sendmail)?;
+    if status.success() { Ok(()) } else { Err(Error::Sendmail(status)) }
+}
+
+/// The space a backup of the given world would take up before compression.
+async fn world_size<S: Server>(options: &Options, world: &S) -> Result<ByteSize, Error> {
+    let world_dir = options.world_dir(world);
+    let Some(world_dir_name) = world_dir.file_name() else { panic!("world directory at root") };
+    Ok(dir_size_excluding(&world_dir, options.excludes.clone(), PathBuf::from(world_dir_name)).await?)
+}
+
+/// Returns the backup path as an rsync destination if it refers to a remote host, using the same syntax as rsync: a colon before the first slash.
+pub fn remote_target(backup_path: &Path) -> Option<String> {
+    let backup_path = backup_path.to_str()?;
+    let (host, _) = backup_path.split_once(':')?;
+    (!host.is_empty() && !host.contains('/')).then(|| backup_path.trim_end_matches('/').to_owned())
+}
+
+/// Backs up the given world to the given remote rsync destination, like `user@host:/media/backup/world`, using `rsync` over SSH.
+///
+/// The remote backup directory isn't inspected at all, so there's no check whether the world has changed, no deleting old backups to make room, and no compression.
+async fn remote_backup<S: Server>(options: &Options, remote: &str, world: &S) -> Result<Summary, Error> {
+    let version = world_version(options, world).await?;
+    let name = format!("{}_{version}", options.timestamp_format.display(options.timestamp.unwrap_or_else(Utc::now)));
+    let target = match options.layout {
+        Layout::PerWorld => format!("{remote}/{world}/{name}"),
+        Layout::Flat => format!("{remote}/{name}"),
+    };
+    debug!("backing up {world} world to {remote}");
+    options.run(options.rsync()
+        .arg("--archive")
+        .arg("--mkpath") // create the world's backup directory if necessary
+        .arg("-e")
+        .arg("ssh")
+        .args(options.excludes.patterns.iter().flat_map(|pattern| ["--exclude", pattern.as_str()]))
+        .arg(options.source_dir(world))
+        .arg(&target), "rsync").await?;
+    Ok(Summary {
+        world: world.to_string(),
+        backup: Some(name),
+        skipped: None,
+        path: Some(target),
+        version: Some(version),
+        size: None,
+        deleted: Vec::default(),
+        free_space: None,
+    })
+}
+
+/// How many of the most recent archives of a world are used to estimate how well a new backup will compress.
+const COMPRESSION_RATIO_SAMPLES: usize = 5;
+/// The lowest ratio of compressed to uncompressed size assumed for a new archive, in case the world suddenly compresses much worse than before.
+const MIN_COMPRESSION_RATIO: f64 = 0.25;
+
+/// The ratio of compressed to uncompressed size of the most recent archives of the given world using the current compression method,
+/// or `None` if none of them have metadata recording their uncompressed size.
+async fn compression_ratio<S: Server>(options: &Options, world: &S) -> Result<Option<f64>, Error> {
+    let mut num_samples = 0;
+    let mut compressed = 0;
+    let mut uncompressed = 0;
+    for backup in options.store(world).scan().await?.0.into_iter().rev().filter(|backup| backup.compression == options.compression) {
+        if num_samples >= COMPRESSION_RATIO_SAMPLES { break }
+        if let Some(Metadata { uncompressed_size: Some(size), .. }) = Metadata::read(&backup.path).await? {
+            compressed += dir_size(&backup.path).await?.as_u64();
+            uncompressed += size;
+            num_samples += 1;
+        }
+    }
+    Ok((uncompressed > 0).then(|| (compressed as f64 / uncompressed as f64).clamp(MIN_COMPRESSION_RATIO, 1.0)))
+}
+
+/// The space which will be written when creating a new backup of the given world.
+///
+/// Uncompressed backups are copied in full before being compressed, so the compression ratio only matters in `--stream-compress` mode.
+async fn backup_size_estimate<S: Server>(options: &Options, world: &S) -> Result<ByteSize, Error> {
+    if options.stream_compress && options.compression != Compression::None {
+        // only the archive is written, so assume the world compresses about as well as recent backups, with a margin
+        if let Some(ratio) = compression_ratio(options, world).await? {
+            let size = (world_size(options, world).await?.as_u64() as f64 * ratio) as u64;
+            return Ok(ByteSize::b(size + size / 10))
+        }
+        // backups created by older versions don't record their uncompressed size, so assume it's about as large as the newest archive of this world, with a margin for growth
+        if let Some(newest) = options.store(world).newest().await? {
+            if newest.compression == options.compression {
+                let size = dir_size(&newest.path).await?;
+                return Ok(size + ByteSize::b(size.as_u64() / 10))
+            }
+        }
+    }
+    world_size(options, world).await
+}
+
+/// Fails with [`Error::DiskUsage`] if writing a backup of the given size would fill more than `--max-disk-usage-percent` of the backup mount,
+/// without considering any space that could be freed by deleting old backups.
+fn check_disk_usage(options: &Options, backup_size: ByteSize) -> Result<(), Error> {
+    let Some(max) = options.max_disk_usage_percent else { return Ok(()) };
+    let total = options.mount()?.total.as_u64().max(1) as f64;
+    let used = total - options.available_space()?.as_u64() as f64;
+    let current = used / total * 100.0;
+    let projected = (used + backup_size.as_u64() as f64) / total * 100.0;
+    if projected > max { Err(Error::DiskUsage { current, projected, max }) } else { Ok(()) }
+}
+
+/// Fails with [`Error::NoSuchWorld`] if the given world's directory doesn't exist, which usually means the world name is mistyped.
+async fn check_world_exists<S: Server>(options: &Options, world: &S) -> Result<(), Error> {
+    if options.world_dir.is_some() { return Ok(()) } // already checked for a level.dat
+    let dir = world.server_dir();
+    if fs::exists(&dir).await? && tokio::fs::metadata(&dir).await.at(&dir)?.is_dir() { Ok(()) } else { Err(Error::NoSuchWorld(world.to_string())) } //TODO wheel
+}
+
+/// Backs up the given world, making sure that at least `reserve` additional space remains free afterwards.
+async fn do_backup<S: Server>(options: &Options, world: &S, reserve: ByteSize) -> Result<Summary, Error> {
+    if let Some(ref remote) = options.remote { return remote_backup(options, remote, world).await }
+    let dir = options.store(world).dir();
+    if !options.force {
+        if let Some(newest) = options.store(world).newest().await? {
+            let world_dir = options.world_dir(world);
+            let Some(world_dir_name) = world_dir.file_name() else { panic!("world directory at root") };
+            // a changed Minecraft version is reflected in the backup filename, so it still warrants a new backup
+            if newest.version == world_version(options, world).await? && DateTime::<Utc>::from(latest_modification(&world_dir, options.excludes.clone(), PathBuf::from(world_dir_name)).await?) <= newest.timestamp {
+                debug!("skipping backup of {world} world since it hasn't changed since the last backup");
+                return Summary::skipped(options, world, SkipReason::Unchanged)
+            }
+        }
+    }
+    options.protected.borrow_mut().clear(); // forget the protected backups of previously backed up worlds
+    // deleting the most recent backup to make room would leave a gap if the new backup fails
+    if let Some(newest) = options.store(world).newest().await? {
+        options.protected.borrow_mut().insert(newest.timestamp);
+    }
+    let backup_size = backup_size_estimate(options, world).await?;
+    options.report_free_space("before making room")?;
+    if make_room(options, backup_size + reserve, world).await? {
+        options.report_free_space("after making room")?;
+        let name = make_backup(options, world).await?;
+        {
+            // the previous newest backup only needed protecting until the new backup succeeded, and keeping it would prevent `max_backups = 1`
+            let mut protected = options.protected.borrow_mut();
+            protected.clear();
+            // don't delete the new backup to make room for compressing older ones
+            protected.insert(options.timestamp_format.parse_filename(&name)?.0);
+        }
+        compress_all(options, world).await?;
+        options.report_free_space("after compressing")?;
+        if let Some(world_config) = options.worlds.get(&world.to_string()) {
+            enforce_retention(options, world, world_config.max_backups, world_config.max_total_size).await?;
+        }
+        let mut path = dir.join(&name);
+        if let Some(extension) = options.compression.extension() {
+            let compressed = dir.join(format!("{name}.{extension}"));
+            if fs::exists(&compressed).await? { path = compressed }
+        }
+        let size = if fs::exists(&path).await? { dir_size(&path).await? } else { ByteSize::default() }; // the backup doesn't exist in --dry-run mode
+        let Some(filename) = path.file_name() else { panic!("backup at root") };
+        Ok(Summary {
+            world: world.to_string(),
+            backup: Some(filename.to_str().ok_or(Error::Utf8)?.to_owned()),
+            skipped: None,
+            path: Some(path.to_str().ok_or(Error::Utf8)?.to_owned()),
+            version: Some(options.timestamp_format.parse_filename(&name)?.1.to_owned()),
+            size: Some(size.as_u64()),
+            deleted: options.deleted.take(),
+            free_space: Some(options.available_space()?.as_u64()),
+        })
+    } else {
+        Err(Error::DiskSpace { required: options.required_space(backup_size + reserve)?, available: options.available_space()? })
+    }
+}
+
+/// Resolves once the process receives SIGINT or SIGTERM.
+///
+/// Once a handler for these signals is registered, they no longer terminate the process, so this should be created once for everything that can be interrupted,
+/// and everything that runs in the meantime should be wrapped using [`Interrupt::guard`] or check [`Interrupt::is_interrupted`].
+#[derive(Clone)]
+pub struct Interrupt(Shared<LocalBoxFuture<'static, ()>>);
+
+impl Interrupt {
+    pub fn new() -> Result<Self, Error> {
+        let mut sigint = signal(SignalKind::interrupt())?;
+        let mut sigterm = signal(SignalKind::terminate())?;
+        Ok(Self(async move {
+            tokio::select! {
+                _ = sigint.recv() => {}
+                _ = sigterm.recv() => {}
+            }
+        }.boxed_local().shared()))
+    }
+
+    /// Runs the given future, cancelling it (which kills any running `rsync` or `tar`) and failing with [`Error::Interrupted`] if a signal is received first.
+    ///
+    /// Signals received before this is called also count, so once interrupted, every guarded future fails immediately.
+    pub async fn guard<T>(&self, f: impl Future<Output = Result<T, Error>>) -> Result<T, Error> {
+        tokio::select! {
+            res = f => res,
+            () = self.0.clone() => Err(Error::Interrupted),
+        }
+    }
+
+    /// Whether a signal has been received, including while nothing was guarded.
+    pub fn is_interrupted(&self) -> bool {
+        self.0.clone().now_or_never().is_some()
+    }
+}
+
+/// Runs the given future with saves disabled if the world is running and `--no-save-toggle` isn't given, reenabling saves afterwards even if it fails.
+///
+/// If `save_settle` is given, the world is saved after disabling saves. If the server doesn't confirm that the save has completed, the given duration is waited instead.
+/// Unless `--no-sync` is given, the world's file system is then synced.
+///
+/// In `--dry-run` mode, saves are left alone so the live server is untouched.
+///
+/// If the process receives SIGINT or SIGTERM, the future is cancelled using [`Interrupt::guard`], saves are reenabled, and [`Error::Interrupted`] is returned.
+/// To check this manually, start a backup of a running world and press Ctrl+C while `rsync` is running; the server log should show saves being turned back on.
+pub async fn with_saves_off<S: Server, T>(options: &Options, world: &S, save_settle: Option<Duration>, interrupt: &Interrupt, f: impl Future<Output = Result<T, Error>>) -> Result<T, Error> {
+    if options.dry_run.is_some() || !options.toggle_saves || !world.is_active().await? { return interrupt.guard(f).await }
+    // the error alone often doesn't say which command failed or why
+    let context = |command| move |inner| Error::ServerCommand { world: world.to_string(), command, inner };
+    let res = interrupt.guard(async {
+        world.send("save-off").await.map_err(context("save-off"))?;
+        if let Some(save_settle) = save_settle {
+            // `save-all flush` only responds once all chunks have been written, but some servers don't support it
+            if !world.send("save-all flush").await.map_err(context("save-all flush"))?.contains("Saved the game") {
+                sleep(save_settle).await;
+            }
+        }
+        if options.sync {
+            // make sure the saved region files have been written from the page cache to the world's file system
+            Command::new("sync").arg("--file-system").arg(options.world_dir(world)).check("sync").await?;
+        }
+        f.await
+    }).await;
+    let save_on_res = world.send("save-on").await.map(|_| ()).map_err(context("save-on")); // reenable saves even if backup failed or was interrupted
+    res.and_then(|value| save_on_res.map(|()| value))
+}
+
+/// The worlds with a backup directory in the backup path, sorted by name, whether or not they're still managed by systemd-minecraft.
+///
+/// With [`Layout::Flat`], the world can't be determined from the backup path, so this fails with [`Error::FlatLayout`].
+pub async fn backed_up_worlds<S: Server>(options: &Options) -> Result<Vec<S>, Error> {
+    if options.layout == Layout::Flat { return Err(Error::FlatLayout) }
+    let mut world_names = Vec::default();
+    let mut entries = pin!(fs::read_dir(&options.backup_path));
+    while let Some(entry) = entries.try_next().await? {
+        if !entry.file_type().await.at(entry.path())?.is_dir() { continue } // skip lock files and the audit log
+        let world_name = entry.file_name().into_string()?;
+        if world_name == "lost+found" { continue }
+        world_names.push(world_name);
+    }
+    world_names.sort();
+    Ok(world_names.into_iter().map(S::new).collect())
+}
+
+/// Finds all worlds managed by systemd-minecraft by looking for world directories next to the given world's directory.
+pub async fn all_worlds<S: Server>(sibling: &S) -> Result<Vec<S>, Error> {
+    let sibling_dir = sibling.server_dir();
+    let Some(worlds_dir) = sibling_dir.parent() else { panic!("world directory at root") };
+    let mut world_names = Vec::default();
+    let mut entries = pin!(fs::read_dir(worlds_dir));
+    while let Some(entry) = entries.try_next().await? {
+        // skip anything that isn't a world directory set up by systemd-minecraft
+        if entry.file_type().await.at(entry.path())?.is_dir() && fs::exists(entry.path().join("minecraft_server.jar")).await? {
+            world_names.push(entry.file_name().into_string()?);
+        }
+    }
+    world_names.sort();
+    Ok(world_names.into_iter().map(S::new).collect())
+}
+
+/// The checks and cleanup before backing up the given world, which all happen before saves are turned off. Returns a summary if the backup is skipped because of `--min-interval`.
+async fn prepare_backup<S: Server>(options: &Options, world: &S) -> Result<Option<Summary>, Error> {
+    check_world_exists(options, world).await?;
+    if options.server_check && options.toggle_saves && !world.is_active().await? { return Err(Error::ServerStopped(world.to_string())) }
+    for (name, dir) in &options.skip_dimensions {
+        if !fs::exists(options.world_dir(world).join(dir)).await? { warn!("the {world} world has no {name} dimension to skip") }
+    }
+    if options.remote.is_none() {
+        if let Some(min_interval) = options.min_interval.filter(|_| !options.force) {
+            if options.store(world).newest().await?.is_some_and(|newest| Utc::now() - newest.timestamp < min_interval) {
+                info!("skipping backup of {world} world since the newest backup is younger than --min-interval");
+                return Summary::skipped(options, world, SkipReason::MinInterval).map(Some)
+            }
+        }
+        remove_temporaries(options, world).await?;
+        if options.clean_partial { remove_partial(options, world).await? }
+        check_disk_usage(options, backup_size_estimate(options, world).await?)?;
+    }
+    Ok(None)
+}
+
+/// Backs up the given worlds one after another, reserving room for the worlds which are yet to be backed up, and returns the result for each world in order.
+///
+/// A failed backup doesn't prevent the remaining worlds from being backed up, but once the process is interrupted, the remaining worlds are skipped.
+/// The interrupted world's result is [`Error::Interrupted`], unless only its notifications were interrupted, which can be checked using [`Interrupt::is_interrupted`].
+///
+/// Worlds whose servers are stopped are backed up without toggling saves. With `--snapshot`, saves are only off while the snapshot is created.
+pub async fn backup_worlds<S: Server>(options: &Options, worlds: &[S], save_settle: Duration, interrupt: &Interrupt, discord_webhook: Option<&str>, email: Option<&EmailReport>) -> Result<Vec<Result<Summary, Error>>, Error> {
+    // backups of different worlds would be indistinguishable
+    if options.layout == Layout::Flat && worlds.len() > 1 { return Err(Error::FlatLayout) }
+    let mut world_sizes = Vec::with_capacity(worlds.len());
+    for world in worlds {
+        // errors are reported when backing up the world itself
+        world_sizes.push(world_size(options, world).await.unwrap_or_default());
+    }
+    let mut results = Vec::with_capacity(worlds.len());
+    for (idx, world) in worlds.iter().enumerate() {
+        let reserve = world_sizes[idx + 1..].iter().fold(ByteSize::default(), |total, &size| total + size);
+        let res = async {
+            // refuse to start before saves are turned off
+            if let Some(summary) = interrupt.guard(prepare_backup(options, world)).await? { return Ok(summary) }
+            if let Some(kind) = options.snapshot {
+                let snapshot = with_saves_off(options, world, Some(save_settle), interrupt, Snapshot::create(options, kind, &options.world_dir(world))).await?;
+                *options.snapshot_dir.borrow_mut() = Some(snapshot.world_dir.clone());
+                let res = interrupt.guard(do_backup(options, world, reserve)).await;
+                options.snapshot_dir.take();
+                // destroy the snapshot even if the backup failed or was interrupted, so the next backup can create a new one
+                let destroy_res = snapshot.destroy(options).await;
+                res.and_then(|summary| destroy_res.map(|()| summary))
+            } else {
+                with_saves_off(options, world, Some(save_settle), interrupt, do_backup(options, world, reserve)).await
+            }
+        }.await;
+        if let Some(webhook) = discord_webhook {
+            // best-effort, so a webhook failure doesn't mask the backup result
+            match interrupt.guard(async { Ok(notify_discord(options, webhook, world, &res).await) }).await {
+                Ok(Ok(())) | Err(_) => {} // interruptions are handled below
+                // the URL contains the webhook token
+                Ok(Err(e)) => warn!("failed to send Discord notification: {}", e.without_url()),
+            }
+        }
+        if let Some(email) = email {
+            // best-effort like the Discord notification
+            match interrupt.guard(notify_email(options, email, world, &res)).await {
+                Ok(()) | Err(Error::Interrupted) => {}
+                Err(e) => warn!("failed to send email report: {e}"),
+            }
+        }
+        results.push(res);
+        if interrupt.is_interrupted() { break }
+    }
+    Ok(results)
+}
\ No newline at end of file
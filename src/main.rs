@@ -3,257 +3,776 @@
 
 use {
     std::{
-        collections::BTreeMap,
-        ffi::OsString,
-        path::Path,
-        pin::{
-            Pin,
-            pin,
+        cell::RefCell,
+        env,
+        io::{
+            self,
+            IsTerminal as _,
+            Write as _,
         },
+        iter,
+        path::PathBuf,
+        process,
+        sync::Arc,
         time::Duration,
     },
     bytesize::ByteSize,
     chrono::prelude::*,
-    futures::{
-        future::Future,
-        stream::TryStreamExt as _,
-    },
     itertools::Itertools as _,
     lazy_regex::regex_captures,
     systemd_minecraft::World,
-    systemstat::{
-        Platform as _,
-        System,
-    },
-    tokio::{
-        process::Command,
-        time::sleep,
+    tracing::{
+        Level,
+        debug,
+        error,
+        info,
     },
     wheel::{
         fs,
-        traits::{
-            AsyncCommandOutputExt as _,
-            IoResultExt as _,
-        },
+        traits::IoResultExt as _,
+    },
+    wurstminebackup::{
+        AUDIT_LOG_FILENAME,
+        AuditLog,
+        BackupInfo,
+        CheckStatus,
+        Compression,
+        DISCORD_WEBHOOK_CREDENTIAL,
+        DISCORD_WEBHOOK_ENV,
+        EmailReport,
+        Error,
+        Excludes,
+        Format,
+        Interrupt,
+        IoPriority,
+        Layout,
+        ListedBackup,
+        Lock,
+        Options,
+        RetentionStrategy,
+        Server,
+        SnapshotKind,
+        Summary,
+        TimeRange,
+        TimestampFormat,
+        all_worlds,
+        backed_up_worlds,
+        backup_worlds,
+        check_downgrade,
+        clean,
+        compress_all,
+        config::Config,
+        dedup,
+        diff,
+        dimension_dir,
+        dimension_exclude,
+        export,
+        health_check,
+        import,
+        list,
+        on_path,
+        prune,
+        remote_target,
+        restore,
+        secret,
+        set_pinned,
+        stats,
+        verify,
+        with_saves_off,
+        write_metrics,
     },
 };
+// only used by the library
+use {
+    fs2 as _,
+    futures as _,
+    globset as _,
+    reqwest as _,
+    serde as _,
+    sha2 as _,
+    systemstat as _,
+    thiserror as _,
+    toml as _,
+};
+#[cfg(test)] use tempfile as _;
 
-const BACKUP_PATH: &str = "/media/backup/world";
-const TIMESTAMP_FORMAT: &str = "%Y-%m-%d_%H-%M-%S";
-
-#[derive(Debug, thiserror::Error)]
-enum Error {
-    #[error(transparent)] ChronoParse(#[from] chrono::format::ParseError),
-    #[error(transparent)] Minecraft(#[from] systemd_minecraft::Error),
-    #[error(transparent)] Wheel(#[from] wheel::Error),
-    #[error("not enough room to create a backup")]
-    DiskSpace,
-    #[error("found file in backup path not matching the filename format")]
-    FilenameFormat,
-    #[error("unexpected minecraft_server.jar filename format")]
-    JarPath,
-    #[error("failed to check file system stats at backup directory")]
-    NoMount,
-    #[error("non-UTF-8 filename")]
-    OsString(OsString),
-    #[error("non-UTF-8 filename")]
-    Utf8,
+#[derive(clap::Parser)]
+#[clap(version, args_conflicts_with_subcommands = true)]
+struct Args {
+    /// Log what's being done. Specify twice to also log details like the output of `rsync` and `tar`.
+    #[clap(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Only log warnings and errors
+    #[clap(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Only print the commands that would be run and the backups that would be deleted, without changing anything
+    #[clap(long, global = true)]
+    dry_run: bool,
+    /// Don't ask for confirmation before deleting backups using `clean` or `prune` or restoring a backup into the world directory. Required if there's no terminal to ask on.
+    #[clap(short, long, global = true)]
+    yes: bool,
+    /// With `json`, a single object is printed to stdout, whose `worlds` array has a summary of each backed up world, or an `error` for worlds which failed. Logs are always written to stderr.
+    #[clap(long, value_enum, global = true, default_value_t = Format::Text)]
+    format: Format,
+    /// How to compress backups after they've been created
+    #[clap(long, value_enum, default_value_t = Compression::Gzip)]
+    compression: Compression,
+    /// An rsync filter pattern for files in the world directory which shouldn't be backed up. Can be specified multiple times, in addition to any patterns from the config file.
+    #[clap(long, value_name = "PATTERN")]
+    exclude: Vec<String>,
+    /// Don't back up the dimension with this name, like `the_end` or `mymod:voidworld`. Can be specified multiple times.
+    #[clap(long, value_name = "NAME")]
+    skip_dimension: Vec<String>,
+    /// A program which `tar` uses to compress backups, e.g. `pigz` or `pzstd`. It must produce the format selected by `--compression`.
+    ///
+    /// Defaults to `pigz` for gzip or `pzstd` for zstd if they're installed, since they use multiple threads.
+    #[clap(long, value_name = "CMD")]
+    compress_program: Option<String>,
+    /// Trade CPU time for archive size, from 1 (fastest) to 9 for gzip or 19 for zstd. Defaults to the compression program's default.
+    #[clap(long, value_name = "N")]
+    compression_level: Option<u8>,
+    /// How many backups to compress at the same time, if there's enough room
+    #[clap(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    compress_jobs: u16,
+    /// How to choose which backup to delete when making room or pruning. The default deletes the backup closest to its neighbors, where different Minecraft versions are always far apart.
+    #[clap(long, value_name = "STRATEGY", value_enum, default_value_t = RetentionStrategy::VersionFirst, global = true)]
+    retention_strategy: RetentionStrategy,
+    /// Never delete the N newest backups of each world when making room or pruning, even if they're close to each other
+    #[clap(long, value_name = "N", default_value_t = 0, global = true)]
+    keep_last: usize,
+    /// Leave the N newest backups of each world uncompressed, so they can be restored quickly
+    #[clap(long, value_name = "N", default_value_t = 0)]
+    keep_uncompressed: usize,
+    /// When making room for a new backup, compress the largest uncompressed backup that there's room to compress before deleting any backups
+    #[clap(long)]
+    compress_before_delete: bool,
+    /// Write a `.sha256` file next to each newly compressed backup
+    #[clap(long)]
+    checksum: bool,
+    /// Check that each newly compressed backup contains the world's level.dat before deleting the uncompressed backup. This reads the entire archive again.
+    #[clap(long)]
+    verify_compressed: bool,
+    /// Create a backup even if the world hasn't changed since the last one
+    #[clap(short, long)]
+    force: bool,
+    /// Don't back up a world if its newest backup is younger than this, e.g. `1h`, in case a timer fires too often. Ignored with --force.
+    #[clap(long, value_name = "DURATION", value_parser = parse_duration)]
+    min_interval: Option<chrono::Duration>,
+    /// Before backing up, delete backups left incomplete by a crashed run: empty directories and archives which tar reports as damaged. Every archive is read in full, and pinned backups are kept.
+    #[clap(long)]
+    clean_partial: bool,
+    /// Label the new backup with this time, e.g. `2024-01-31T12:00:00Z`, instead of the current time
+    #[clap(long, value_name = "RFC3339", value_parser = parse_rfc3339, hide = true)]
+    timestamp: Option<DateTime<Utc>>,
+    /// Hardlink files which haven't changed since the previous backup instead of copying them
+    ///
+    /// This keeps the newest backup uncompressed, since it's used as the base for the next backup.
+    /// Deleting a backup never breaks the others, since hardlinked files remain until their last link is deleted,
+    /// but it only frees the space of files which aren't shared with other backups.
+    #[clap(long, conflicts_with = "stream_compress")]
+    incremental: bool,
+    /// Archive the world directly using `tar` instead of copying it using `rsync` and compressing the copy afterwards
+    ///
+    /// This roughly halves the peak disk usage of a backup, since there's never an uncompressed copy of the world next to the archive,
+    /// but the world is read by `tar` while saves are off instead of `rsync`, so `rsync`'s retries on partial transfers don't apply.
+    /// Exclude patterns are interpreted by `tar`, which only matters for complex patterns.
+    #[clap(long)]
+    stream_compress: bool,
+    /// How long to wait for the server to finish saving if it doesn't confirm the save. Defaults to 10 seconds.
+    ///
+    /// If this is too short, the backup may contain partially written region files.
+    #[clap(long, value_name = "SECONDS")]
+    save_wait: Option<u64>,
+    /// Don't sync the world's file system after saving the world, e.g. if it's mounted with the `sync` option anyway
+    #[clap(long, global = true)]
+    no_sync: bool,
+    /// Back up from a snapshot of the ZFS dataset or btrfs subvolume containing the world, so saves only need to be off while the snapshot is created.
+    ///
+    /// The snapshot is named after the world directory and destroyed after the backup. If a previous run was killed before destroying it, it has to be destroyed manually.
+    #[clap(long, value_name = "FS", value_enum)]
+    snapshot: Option<SnapshotKind>,
+    /// Don't send `save-off`, `save-all`, or `save-on` to the server, e.g. if it's stopped or the world is backed up from a file system snapshot
+    #[clap(long, global = true)]
+    no_save_toggle: bool,
+    /// Fail if a world's server isn't running instead of backing it up without toggling saves, so a crashed server isn't mistaken for one that's deliberately stopped. Use `--no-save-toggle` to back up stopped worlds.
+    #[clap(long, conflicts_with = "no_save_toggle")]
+    world_server_check: bool,
+    /// Stop rerunning `rsync` after this many runs even if files are still changing, so saves aren't kept off indefinitely
+    #[clap(long, value_name = "N", default_value_t = 5, value_parser = clap::value_parser!(u16).range(1..))]
+    max_rsync_passes: u16,
+    /// Kill `rsync` and `tar` if they run for longer than this, e.g. because the backup drive stalled
+    #[clap(long, value_name = "SECONDS", global = true)]
+    command_timeout: Option<u64>,
+    /// Run `rsync` and `tar` with this niceness so they don't slow down the server. 0 to run them at normal priority.
+    #[clap(long, value_name = "N", default_value_t = 10, value_parser = clap::value_parser!(i64).range(-20..=19), allow_negative_numbers = true, global = true)]
+    nice: i64,
+    /// Run `rsync` and `tar` with this I/O scheduling class so they don't slow down the server
+    #[clap(long, value_name = "CLASS", value_enum, default_value_t = IoPriority::BestEffort, global = true)]
+    ionice: IoPriority,
+    /// Limit `rsync` to transferring this much data per second, e.g. `10 MiB`, so it doesn't saturate a network or slow disk
+    #[clap(long, value_name = "RATE", value_parser = parse_bwlimit, global = true)]
+    bwlimit: Option<ByteSize>,
+    /// Delete old backups until at least this much space would remain free after creating the new backup, e.g. `10 GiB`
+    #[clap(long, value_name = "SIZE", default_value_t = ByteSize::default())]
+    min_free_bytes: ByteSize,
+    /// Delete old backups until at least this percentage of the backup drive would remain free after creating the new backup
+    #[clap(long, value_name = "PERCENT", default_value_t = 0.0)]
+    min_free_percent: f64,
+    /// Refuse to start a backup if writing it would fill more than this percentage of the backup drive, even if old backups could be deleted to make room
+    #[clap(long, value_name = "PERCENT")]
+    max_disk_usage_percent: Option<f64>,
+    /// Allow backing up to a backup path on the root file system
+    #[clap(long)]
+    allow_unmounted: bool,
+    /// Allow backing up to a remote rsync destination like `user@host:/media/backup`, skipping disk space checks, retention, and compression
+    #[clap(long)]
+    remote_unchecked: bool,
+    /// A Discord webhook URL to notify with the result of the backup
+    ///
+    /// Since the URL is a secret, it can also be given using the `WURSTMINEBACKUP_DISCORD_WEBHOOK` environment variable or a `discord-webhook` systemd credential,
+    /// which are used in that order before falling back to the config file.
+    #[clap(long)]
+    discord_webhook: Option<String>,
+    /// Send a report of the backup to this email address using `sendmail`, which can be configured using `sendmail` and `email_from` in the config file
+    #[clap(long, value_name = "ADDR")]
+    email: Option<String>,
+    /// Only send an email report if the backup failed
+    #[clap(long, requires = "email")]
+    email_on_failure: bool,
+    /// After backing up, write metrics about the backups of the backed up worlds to this file for the textfile collector of the Prometheus node exporter, like `/var/lib/prometheus/node-exporter/wurstminebackup.prom`
+    #[clap(long, value_name = "PATH")]
+    metrics_file: Option<PathBuf>,
+    /// The directory containing the per-world backup directories, or the backups themselves with `--layout flat`. Defaults to `/media/backup/world`.
+    #[clap(long, global = true)]
+    backup_path: Option<PathBuf>,
+    /// Whether the backups of each world are in a directory named after the world in the backup path (`per-world`) or directly in the backup path (`flat`), which then holds the backups of only one world
+    #[clap(long, value_enum, default_value_t = Layout::PerWorld, global = true)]
+    layout: Layout,
+    /// Path to the config file. Defaults to `/etc/wurstminebackup.toml`.
+    #[clap(long, global = true)]
+    config: Option<PathBuf>,
+    /// A world to back up. Can be specified multiple times or as a comma-separated list, in addition to the positional world.
+    #[clap(long = "world", value_name = "WORLD", value_delimiter = ',', conflicts_with_all = ["world_dir", "jar_path"])]
+    worlds: Vec<String>,
+    /// Back up or restore the world from this directory instead of the one managed by systemd-minecraft. Server commands are still sent to the world's server. Can't be combined with `--world` or `--all`.
+    #[clap(long, value_name = "PATH", global = true)]
+    world_dir: Option<PathBuf>,
+    /// Determine the world's Minecraft version from this server jar instead of the world's minecraft_server.jar, e.g. for modded servers. Can't be combined with `--world` or `--all`.
+    #[clap(long, value_name = "FILE", global = true)]
+    jar_path: Option<PathBuf>,
+    /// Back up all worlds managed by systemd-minecraft
+    #[clap(long, conflicts_with_all = ["worlds", "world", "world_dir", "jar_path"])]
+    all: bool,
+    /// Defaults to `wurstmineberg` unless worlds are given using `--world`.
+    world: Option<String>,
+    #[clap(subcommand)]
+    subcommand: Option<Subcommand>,
 }
 
-impl From<OsString> for Error {
-    fn from(value: OsString) -> Self {
-        Self::OsString(value)
-    }
+#[derive(clap::Subcommand)]
+enum Subcommand {
+    /// Check the newest backup and the backup drive, printing a single line and exiting with the status codes used by Nagios plugins
+    Check {
+        /// Warn if the newest backup is older than this, and fail if it's more than twice as old
+        #[clap(long, value_name = "HOURS", default_value_t = 25)]
+        max_age: u64,
+        /// Defaults to `wurstmineberg`.
+        world: Option<String>,
+    },
+    /// Delete all backups older than the given age, except pinned backups and the newest backup
+    Clean {
+        /// For example `30d`, `12h`, or `2w`
+        #[clap(long, value_name = "DURATION", value_parser = parse_duration)]
+        older_than: chrono::Duration,
+        /// Defaults to `wurstmineberg`.
+        world: Option<String>,
+    },
+    /// Compress the uncompressed backups of a world without creating a new backup, e.g. after importing uncompressed backups
+    ///
+    /// The `--keep-uncompressed` newest backups are left uncompressed. If there's not enough room to compress a backup, old backups of the same world are deleted to make room.
+    Compress {
+        /// Compress the backups of every world with backups in the backup path, one world after another
+        #[clap(long, conflicts_with = "world")]
+        all: bool,
+        /// Defaults to `wurstmineberg`.
+        world: Option<String>,
+    },
+    /// Mark compressed backups with the same contents as the previous backup as duplicates, so they're deleted before any other backups when making room
+    Dedup {
+        /// Defaults to `wurstmineberg`.
+        world: Option<String>,
+    },
+    /// List the files which were added, deleted, or modified between two backups of a world
+    Diff {
+        /// The timestamp of the older backup, in the configured timestamp format (`YYYY-MM-DD_HH-MM-SS` by default) or a unique prefix of it, like `2024-06-01`
+        old: String,
+        /// The timestamp of the newer backup, in the configured timestamp format (`YYYY-MM-DD_HH-MM-SS` by default) or a unique prefix of it, like `2024-06-01`
+        new: String,
+        /// Defaults to `wurstmineberg`.
+        world: Option<String>,
+    },
+    /// Write a backup to a single compressed archive, e.g. to move it to another host
+    Export {
+        /// Print the SHA-256 checksum of the archive
+        #[clap(long)]
+        checksum: bool,
+        /// Overwrite the output file if it exists
+        #[clap(short, long)]
+        force: bool,
+        /// The timestamp of the backup to export, in the configured timestamp format (`YYYY-MM-DD_HH-MM-SS` by default) or a unique prefix of it, like `2024-06-01`
+        timestamp: String,
+        /// The path of the archive to create, like `world.tar.gz`. Its extension (`.tar.gz`, `.tar.zst`, or `.tar`) selects the compression method.
+        output: PathBuf,
+        /// Defaults to `wurstmineberg`.
+        world: Option<String>,
+    },
+    /// Add an archive created elsewhere, e.g. using `export`, to the backups of a world
+    Import {
+        /// The Minecraft version of the world in the archive
+        #[clap(long)]
+        version: String,
+        /// The time at which the backup was taken, in the configured timestamp format (`YYYY-MM-DD_HH-MM-SS` by default)
+        #[clap(long)]
+        timestamp: String,
+        /// A `.tar.gz` or `.tar.zst` archive containing a world folder with a `level.dat`
+        archive: PathBuf,
+        /// Defaults to `wurstmineberg`.
+        world: Option<String>,
+    },
+    /// List existing backups of a world
+    List {
+        /// Only list backups from this timestamp or date (like `2024-01-31`) onwards
+        #[clap(long, value_name = "DATE")]
+        since: Option<String>,
+        /// Only list backups up to this timestamp or date (inclusive)
+        #[clap(long, value_name = "DATE")]
+        until: Option<String>,
+        /// Defaults to `wurstmineberg`.
+        world: Option<String>,
+    },
+    /// Exclude a backup from being deleted automatically
+    Pin {
+        /// The timestamp of the backup to pin, in the configured timestamp format (`YYYY-MM-DD_HH-MM-SS` by default) or a unique prefix of it, like `2024-06-01`
+        timestamp: String,
+        /// Defaults to `wurstmineberg`.
+        world: Option<String>,
+    },
+    /// Delete backups until at most the given number remain, preferring to delete those closest to other backups
+    Prune {
+        /// The number of backups to keep. With `--since` or `--until`, this only counts backups within that range, and defaults to deleting all of them.
+        #[clap(long, required_unless_present_any = ["since", "until"])]
+        keep: Option<usize>,
+        /// Only delete backups from this timestamp or date (like `2024-01-31`) onwards
+        #[clap(long, value_name = "DATE")]
+        since: Option<String>,
+        /// Only delete backups up to this timestamp or date (inclusive), e.g. a month ago to prune everything older than that
+        #[clap(long, value_name = "DATE")]
+        until: Option<String>,
+        /// Defaults to `wurstmineberg`.
+        world: Option<String>,
+    },
+    /// Restore a backup into the world directory
+    #[clap(group(clap::ArgGroup::new("backup").args(["timestamp", "nth"])))]
+    Restore {
+        /// Overwrite the existing world directory or target directory, or existing files with `--only`
+        #[clap(short, long)]
+        force: bool,
+        /// Restore into the world directory even if the backup is of an older Minecraft version than the server
+        #[clap(long)]
+        allow_downgrade: bool,
+        /// Restore the Nth most recent backup, where 1 is the newest, instead of the backup with the given timestamp
+        #[clap(long, value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+        nth: Option<u16>,
+        /// Only restore the paths in the world directory matching this pattern, like `region/r.0.0.mca` or `DIM-1/*`, leaving everything else untouched. Can be specified multiple times.
+        #[clap(long, value_name = "GLOB")]
+        only: Vec<String>,
+        /// Restore into this directory instead of the world directory, e.g. to inspect the backup. The server isn't touched in this mode.
+        #[clap(long, value_name = "DIR")]
+        target: Option<PathBuf>,
+        /// The timestamp of the backup to restore, in the configured timestamp format (`YYYY-MM-DD_HH-MM-SS` by default) or a unique prefix of it, like `2024-06-01`. Defaults to the newest backup.
+        timestamp: Option<String>,
+        /// The world to restore. Defaults to `wurstmineberg`.
+        #[clap(long)]
+        world: Option<String>,
+    },
+    /// Summarize the existing backups of a world
+    Stats {
+        /// Defaults to `wurstmineberg`.
+        world: Option<String>,
+    },
+    /// Allow a pinned backup to be deleted automatically again
+    Unpin {
+        /// The timestamp of the backup to unpin, in the configured timestamp format (`YYYY-MM-DD_HH-MM-SS` by default) or a unique prefix of it, like `2024-06-01`
+        timestamp: String,
+        /// Defaults to `wurstmineberg`.
+        world: Option<String>,
+    },
+    /// Check that backups can be read in full
+    Verify {
+        /// Defaults to `wurstmineberg`.
+        world: Option<String>,
+        /// The timestamp of the backup to verify, in the configured timestamp format (`YYYY-MM-DD_HH-MM-SS` by default) or a unique prefix of it, like `2024-06-01`. Defaults to verifying all backups.
+        timestamp: Option<String>,
+    },
 }
 
-//FROM https://docs.rs/fs_extra/1.3.0/src/fs_extra/dir.rs.html#786-816 modified to be async and use ByteSize
-fn dir_size(path: impl AsRef<Path>) -> Pin<Box<dyn Future<Output = wheel::Result<ByteSize>>>> {
-    let path = path.as_ref().to_owned();
-    Box::pin(async {
-        // Using `fs::symlink_metadata` since we don't want to follow symlinks,
-        // as we're calculating the exact size of the requested path itself.
-        let path_metadata = fs::symlink_metadata(&path).await?;
-
-        let mut size_in_bytes = ByteSize::default();
-
-        if path_metadata.is_dir() {
-            let mut read_dir = pin!(fs::read_dir(path));
-            while let Some(entry) = read_dir.try_next().await? {
-                // `DirEntry::metadata` does not follow symlinks (unlike `fs::metadata`), so in the
-                // case of symlinks, this is the size of the symlink itself, not its target.
-                let entry_metadata = entry.metadata().await.at(entry.path())?; //TODO wheel
+fn parse_rfc3339(timestamp: &str) -> Result<DateTime<Utc>, chrono::format::ParseError> {
+    Ok(DateTime::parse_from_rfc3339(timestamp)?.with_timezone(&Utc))
+}
 
-                if entry_metadata.is_dir() {
-                    // The size of the directory entry itself will be counted inside the `get_size()` call,
-                    // so we intentionally don't also add `entry_metadata.len()` to the total here.
-                    size_in_bytes += dir_size(entry.path()).await?;
-                } else {
-                    size_in_bytes += entry_metadata.len();
-                }
-            }
-        } else {
-            size_in_bytes = ByteSize::b(path_metadata.len());
-        }
+fn parse_bwlimit(rate: &str) -> Result<ByteSize, String> {
+    let rate = rate.parse::<ByteSize>()?;
+    // rsync treats 0 as no limit and can't limit to less than 1 KiB per second
+    if rate.as_u64() < 1024 { return Err(String::from("must be at least 1 KiB")) }
+    Ok(rate)
+}
 
-        Ok(size_in_bytes)
-    })
+/// Parses a duration like `30d`, consisting of a number and a unit: `s`, `m`, `h`, `d`, or `w`.
+fn parse_duration(duration: &str) -> Result<chrono::Duration, String> {
+    let Some((_, amount, unit)) = regex_captures!("^([0-9]+)([smhdw])$", duration) else { return Err(String::from("expected a number followed by s, m, h, d, or w")) };
+    let unit_secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => unreachable!("matched by regex"),
+    };
+    let secs = amount.parse::<i64>().ok().and_then(|amount| amount.checked_mul(unit_secs)).filter(|&secs| secs <= i64::MAX / 1000).ok_or_else(|| String::from("duration too long"))?;
+    Ok(chrono::Duration::seconds(secs))
 }
 
-/// Deletes the backup that's closest to other backups. In case of a tie, the oldest backup is deleted.
+/// Prints the results of backing up the given worlds, as returned by [`backup_worlds`].
 ///
-/// If only one backup exists, it's not deleted and `false` is returned.
-async fn delete_one(verbose: bool, world: &World) -> Result<bool, Error> {
-    let dir = Path::new(BACKUP_PATH).join(world.to_string());
-    let mut timestamps = BTreeMap::default();
-    let mut entries = pin!(fs::read_dir(&dir));
-    while let Some(entry) = entries.try_next().await? {
-        let filename = entry.file_name().into_string()?;
-        let (_, timestamp, version) = regex_captures!(r"^([0-9]{4}-[0-9]{2}-[0-9]{2}_[0-9]{2}-[0-9]{2}-[0-9]{2})_(.+?)(?:\.tar\.gz)?$", &filename).ok_or(Error::FilenameFormat)?;
-        if let Ok(mut version_parts) = version.split('.').map(|part| part.parse::<i64>()).try_collect::<_, Vec<_>, _>() {
-            version_parts.resize(3, 0);
-            let [major, minor, patch] = <[_; 3]>::try_from(version_parts).unwrap();
-            timestamps.insert((major, minor, patch, Utc.datetime_from_str(timestamp, TIMESTAMP_FORMAT)?), filename);
-        } else {
-            return Err(Error::FilenameFormat)
-        }
-    }
-    let filename = match timestamps.len() {
-        0 | 1 => return Ok(false),
-        2 => timestamps.into_values().next().unwrap(),
-        _ => timestamps.iter().tuple_windows().min_by_key(|&((&prev, _), (&curr, _), (&next, _))| {
-            fn distance([(old_major, old_minor, old_patch, old_time), (new_major, new_minor, new_patch, new_time)]: [(i64, i64, i64, DateTime<Utc>); 2]) -> (i64, i64, i64, chrono::Duration) {
-                let major_distance = new_major - old_major;
-                let minor_distance = if new_major == old_major { new_minor - old_minor } else { 0 };
-                let patch_distance = if new_major == old_major && new_minor == old_minor { new_patch - old_patch } else { 0 };
-                (major_distance, minor_distance, patch_distance, new_time - old_time)
-            }
-
-            let mut distances = [distance([prev, curr]), distance([curr, next])];
-            distances.sort();
-            distances
-        }).unwrap().1.1.clone(),
-    };
-    if verbose {
-        println!("deleting {filename}");
+/// Fails with the error of the only world, or with [`Error::Worlds`] if backing up any of multiple worlds failed. If more than one world is given, a summary is logged at the end.
+fn report_backups<S: Server>(options: &Options, worlds: &[S], format: Format, interrupt: &Interrupt, results: Vec<Result<Summary, Error>>) -> Result<(), Error> {
+    if format == Format::Json {
+        // a single document, so the output can be parsed as a whole even if some worlds failed
+        let worlds = worlds.iter().zip(&results).map(|(world, res)| match res {
+            Ok(summary) => serde_json::to_value(summary),
+            Err(e) => Ok(serde_json::json!({"world": world.to_string(), "error": e.to_string()})),
+        }).try_collect::<_, Vec<_>, _>()?;
+        println!("{}", serde_json::json!({"worlds": worlds}));
     }
-    let path = dir.join(filename);
-    if fs::symlink_metadata(&path).await?.is_dir() {
-        fs::remove_dir_all(path).await?;
-    } else {
-        fs::remove_file(path).await?;
+    for (world, res) in worlds.iter().zip(&results) {
+        match res {
+            // printed even without --verbose so running this manually gives some confirmation
+            Ok(Summary { path: Some(path), version: Some(version), size, .. }) => if format == Format::Text {
+                println!("{} {world} world to {path} (Minecraft {version}{})", if options.dry_run.is_some() { "would have backed up" } else { "backed up" }, size.filter(|_| options.dry_run.is_none()).map(|size| format!(", {}", ByteSize::b(size))).unwrap_or_default());
+            },
+            Ok(_) => {}
+            Err(e) => if worlds.len() > 1 {
+                error!("failed to back up {world} world: {e}");
+            },
+        }
     }
-    Ok(true)
-}
-
-async fn make_backup(verbose: bool, world: &World) -> Result<(), Error> {
-    let jar_path = world.dir().join("minecraft_server.jar");
-    let jar_path = fs::read_link(&jar_path).await?;
-    let now = Utc::now();
-    let (_, version) = jar_path.file_stem().ok_or(Error::JarPath)?.to_str().ok_or(Error::Utf8)?.split_once('.').ok_or(Error::JarPath)?;
-    if verbose {
-        println!("backing up {world} world");
+    if interrupt.is_interrupted() { return Err(Error::Interrupted) }
+    if worlds.len() == 1 {
+        results.into_iter().next().expect("one result per world")?;
+        return Ok(())
     }
-    loop {
-        let output = Command::new("rsync")
-            .arg("--delete")
-            .arg("--archive")
-            .arg("--itemize-changes")
-            .arg(world.dir())
-            .arg(Path::new(BACKUP_PATH).join(world.to_string()).join(format!("{}_{}", now.format(TIMESTAMP_FORMAT), version)))
-            .check("rsync").await?;
-        if output.stdout.is_empty() { break }
+    if format == Format::Text {
+        for (world, res) in worlds.iter().zip(&results) {
+            match res {
+                Ok(Summary { backup: Some(backup), .. }) => info!("{world}: backed up as {backup}"),
+                Ok(Summary { backup: None, skipped, .. }) => info!("{world}: skipped{}", skipped.map(|reason| format!(" because {}", reason.description())).unwrap_or_default()),
+                Err(_) => info!("{world}: failed"),
+            }
+        }
     }
-    Ok(())
+    let num_failed = results.iter().filter(|res| res.is_err()).count();
+    if num_failed > 0 { Err(Error::Worlds(num_failed)) } else { Ok(()) }
 }
 
-async fn compress_all(verbose: bool, world: &World) -> Result<(), Error> {
-    let dir = Path::new(BACKUP_PATH);
-
-    'outer: loop {
-        let mut entries = pin!(fs::read_dir(dir));
-        let mut smallest_uncompressed = None;
-        while let Some(entry) = entries.try_next().await? {
-            let path = entry.path();
-            let mut entries = pin!(fs::read_dir(path));
-            while let Some(entry) = entries.try_next().await? {
-                let path = entry.path();
-                if entry.file_type().await.at(&path)?.is_dir() {
-                    let size = dir_size(&path).await?;
-                    if smallest_uncompressed.as_ref().map_or(true, |&(_, smallest_size)| size < smallest_size) {
-                        smallest_uncompressed = Some((path, size));
-                    }
-                }
-            }
-        }
-        let Some((path, size)) = smallest_uncompressed else { break };
-        let Some(filename) = path.file_name() else { panic!("backup at root") };
-        let parent = path.parent().unwrap();
-        while dir.ancestors().map(|ancestor| System::new().mount_at(ancestor)).find_map(Result::ok).ok_or(Error::NoMount)?.avail < size {
-            // not enough room to compress anything, delete backups to make room
-            if !delete_one(verbose, world).await? { return Err(Error::DiskSpace) }
-            if !fs::exists(&path).await? { continue 'outer }
+#[wheel::main(debug)]
+async fn main(args: Args) -> Result<(), Error> {
+    let is_check = matches!(args.subcommand, Some(Subcommand::Check { .. }));
+    let res = run::<World>(args).await;
+    if let Err(ref e) = res {
+        if is_check {
+            // the check itself failed, e.g. because the config couldn't be loaded
+            println!("UNKNOWN - {e}");
+            process::exit(3)
         }
-        if verbose {
-            println!("compressing {}", filename.to_string_lossy());
+        if let Some(exit_code) = e.exit_code() {
+            error!("{e}");
+            debug!("{e:?}");
+            process::exit(exit_code)
         }
-        Command::new("tar")
-            .arg(if verbose { "-cvzf" } else { "-czf" })
-            .arg(format!("{}.tar.gz", filename.to_str().ok_or(Error::Utf8)?))
-            .arg(filename)
-            .current_dir(parent)
-            .check("tar").await?;
-        fs::remove_dir_all(path).await?;
     }
-    Ok(())
+    res
 }
 
-/// Backups will be deleted until:
-///
-/// * at least `amount` gibibytes are free _and_ at least `amount` % of the disk is free (returns `Ok(true)`),
-/// * only one backup file is remaining (returns `Ok(false)`), or
-/// * an error occurs (returns `Err(_)`).
-async fn make_room(amount: ByteSize, verbose: bool, world: &World) -> Result<bool, Error> {
-    let dir = Path::new(BACKUP_PATH);
-    while dir.ancestors().map(|ancestor| System::new().mount_at(ancestor)).find_map(Result::ok).ok_or(Error::NoMount)?.avail < amount {
-        if !delete_one(verbose, world).await? { return Ok(false) }
-    }
-    Ok(true)
+/// Asks a yes/no question on the terminal, failing with [`Error::Unconfirmed`] unless the answer is yes or if there's no terminal.
+fn confirm(question: &str) -> Result<(), Error> {
+    if !io::stdin().is_terminal() || !io::stdout().is_terminal() { return Err(Error::Unconfirmed) }
+    print!("{question} [y/N] ");
+    io::stdout().flush()?;
+    let mut answer = String::default();
+    io::stdin().read_line(&mut answer)?;
+    if matches!(&*answer.trim().to_lowercase(), "y" | "yes") { Ok(()) } else { Err(Error::Unconfirmed) }
 }
 
-#[derive(clap::Parser)]
-#[clap(version)]
-struct Args {
-    #[clap(short, long)]
-    verbose: bool,
-    #[clap(default_value = "wurstmineberg")]
-    world: String,
+/// Ends the `--dry-run` preview of a subcommand which deletes backups, asking for confirmation to delete the backups it would have deleted. Returns `false` if there's nothing to delete.
+fn confirm_deletions<S: Server>(options: &mut Options, world: &S) -> Result<bool, Error> {
+    options.dry_run = None;
+    options.protected.get_mut().clear();
+    let deleted = options.deleted.take();
+    if deleted.is_empty() { return Ok(false) }
+    // printed along with the prompt since the dry run only logs them, which --quiet hides
+    for filename in &deleted {
+        println!("{filename}");
+    }
+    confirm(&format!("delete these {} backup(s) of the {world} world?", deleted.len()))?;
+    Ok(true)
 }
 
-async fn do_backup(verbose: bool, world: &World) -> Result<(), Error> {
-    let world_size = dir_size(world.dir()).await?;
-    if make_room(world_size, verbose, world).await? {
-        make_backup(verbose, world).await?;
-        compress_all(verbose, world).await?;
-        Ok(())
+async fn run<S: Server>(Args { verbose, quiet, dry_run, yes, format, compression, compress_program, compression_level, compress_jobs, retention_strategy, keep_last, keep_uncompressed, compress_before_delete, exclude, skip_dimension, checksum, verify_compressed, force, min_interval, clean_partial, timestamp, incremental, stream_compress, save_wait, no_sync, snapshot, no_save_toggle, world_server_check, max_rsync_passes, command_timeout, nice, ionice, bwlimit, min_free_bytes, min_free_percent, max_disk_usage_percent, allow_unmounted, remote_unchecked, discord_webhook, email, email_on_failure, metrics_file, backup_path, layout, config, worlds, world_dir, jar_path, all, world, subcommand }: Args) -> Result<(), Error> {
+    tracing_subscriber::fmt()
+        .with_max_level(if quiet { Level::WARN } else { match verbose { 0 => Level::INFO, 1 => Level::DEBUG, _ => Level::TRACE } })
+        .with_writer(io::stderr)
+        .with_target(false)
+        .without_time() // journald adds its own timestamps
+        .init();
+    let config = Config::load(config.as_deref()).await?;
+    let timestamp_format = TimestampFormat::new(&config.timestamp_format)?;
+    let backup_path = backup_path.unwrap_or(config.backup_path);
+    let remote = remote_target(&backup_path);
+    let is_check = matches!(subcommand, Some(Subcommand::Check { .. }));
+    let backup_path = if remote.is_some() {
+        if subcommand.is_some() { return Err(Error::Remote("this subcommand")) }
+        if stream_compress || incremental { return Err(Error::Remote(if stream_compress { "--stream-compress" } else { "--incremental" })) }
+        if metrics_file.is_some() { return Err(Error::Remote("--metrics-file")) }
+        if !remote_unchecked { return Err(Error::RemoteUnchecked) }
+        backup_path
     } else {
-        Err(Error::DiskSpace)
+        if !fs::exists(&backup_path).await? {
+            if is_check {
+                println!("{} - {}", CheckStatus::Crit, Error::NoBackupPath(backup_path));
+                process::exit(CheckStatus::Crit.exit_code())
+            }
+            return Err(Error::NoBackupPath(backup_path))
+        }
+        tokio::fs::canonicalize(&backup_path).await.at(&backup_path)? //TODO wheel
+    };
+    if let Some(level) = compression_level {
+        if !compression.levels().is_some_and(|levels| levels.contains(&level)) { return Err(Error::CompressionLevel { level, compression }) }
+    }
+    let skip_dimensions = skip_dimension.into_iter().map(|name| dimension_dir(&name).map(|dir| (name, dir))).try_collect::<_, Vec<_>, _>()?;
+    let mut options = Options {
+        dry_run: dry_run.then(RefCell::default),
+        command_timeout: command_timeout.map(Duration::from_secs),
+        compress_jobs: compress_jobs.into(),
+        // the newest backup is the --link-dest of the next one
+        keep_uncompressed: keep_uncompressed.max(usize::from(incremental)),
+        compress_program: compress_program.or_else(|| match compression {
+            Compression::Gzip => on_path("pigz").then(|| String::from("pigz")),
+            Compression::Zstd => on_path("pzstd").then(|| String::from("pzstd")),
+            Compression::None => None,
+        }),
+        sync: !no_sync,
+        toggle_saves: !no_save_toggle,
+        server_check: world_server_check,
+        snapshot_dir: RefCell::default(),
+        max_rsync_passes: max_rsync_passes.into(),
+        worlds: config.worlds,
+        world_dir,
+        jar_path,
+        excludes: Arc::new(Excludes::new(config.exclude.into_iter().chain(exclude).chain(skip_dimensions.iter().map(|(_, dir)| dimension_exclude(dir))).collect())?),
+        skip_dimensions,
+        deleted: RefCell::default(),
+        keep_last,
+        protected: RefCell::default(),
+        audit_log: config.audit_log.then(|| config.audit_log_path.or_else(|| remote.is_none().then(|| backup_path.join(AUDIT_LOG_FILENAME)))).flatten().map(|path| AuditLog { path, max_size: config.audit_log_max_size }),
+        backup_path, layout, timestamp_format, remote, retention_strategy, snapshot, compression, checksum, verify_compressed, force, min_interval, clean_partial, timestamp, incremental, stream_compress, compression_level, compress_before_delete, nice, ionice, bwlimit, min_free_bytes, min_free_percent, max_disk_usage_percent,
+    };
+    debug!("running rsync and tar with niceness {nice} and I/O scheduling class {ionice:?}");
+    match subcommand {
+        None => {
+            let worlds = if all {
+                all_worlds(&S::new(config.default_world)).await?
+            } else {
+                let mut world_names = world.into_iter().chain(worlds).collect_vec();
+                if world_names.is_empty() { world_names.push(config.default_world) }
+                world_names.into_iter().map(S::new).collect()
+            };
+            if let Some(ref world_dir) = options.world_dir {
+                if !fs::exists(world_dir.join("level.dat")).await? { return Err(Error::NotAWorld(world_dir.clone())) }
+            }
+            // refuse to fill the root disk if the backup drive isn't mounted
+            if !allow_unmounted && options.remote.is_none() { options.check_mounted()? }
+            let discord_webhook = match discord_webhook {
+                Some(discord_webhook) => Some(discord_webhook),
+                None => secret(DISCORD_WEBHOOK_ENV, DISCORD_WEBHOOK_CREDENTIAL).await?.or(config.discord_webhook),
+            };
+            let email = email.map(|to| EmailReport { to, from: config.email_from, sendmail: config.sendmail, only_on_failure: email_on_failure });
+            let _locks = if dry_run || options.remote.is_some() { Vec::default() } else { worlds.iter().map(|world| Lock::acquire(&options.backup_path, world)).try_collect::<_, Vec<_>, _>()? };
+            let interrupt = Interrupt::new()?;
+            let res = backup_worlds(&options, &worlds, Duration::from_secs(save_wait.unwrap_or(config.save_settle_secs)), &interrupt, discord_webhook.as_deref(), email.as_ref()).await
+                .and_then(|results| report_backups(&options, &worlds, format, &interrupt, results));
+            // the metrics are also useful after a failed backup, since the age of the newest backup shows how long backups have been failing
+            if let Some(metrics_file) = metrics_file {
+                let metrics_res = write_metrics(&options, &worlds, &env::current_dir()?.join(metrics_file)).await;
+                res?;
+                metrics_res
+            } else {
+                res
+            }
+        }
+        Some(Subcommand::Check { max_age, world }) => {
+            let (status, messages) = health_check(&options, &S::new(world.unwrap_or(config.default_world)), Duration::from_secs(max_age * 60 * 60), allow_unmounted).await?;
+            println!("{status} - {}", messages.join("; "));
+            if status != CheckStatus::Ok { process::exit(status.exit_code()) }
+            Ok(())
+        }
+        Some(Subcommand::Clean { older_than, world }) => {
+            let world = S::new(world.unwrap_or(config.default_world));
+            let _lock = if dry_run { None } else { Some(Lock::acquire(&options.backup_path, &world)?) };
+            if !dry_run && !yes {
+                // the --dry-run output shows what would be deleted
+                options.dry_run = Some(RefCell::default());
+                clean(&options, &world, older_than).await?;
+                if !confirm_deletions(&mut options, &world)? { return Ok(()) }
+            }
+            clean(&options, &world, older_than).await
+        }
+        Some(Subcommand::Compress { all, world }) => {
+            let worlds = if all { backed_up_worlds(&options).await? } else { vec![S::new(world.unwrap_or(config.default_world))] };
+            for world in &worlds {
+                let _lock = if dry_run { None } else { Some(Lock::acquire(&options.backup_path, world)?) };
+                // like when backing up, making room for compression must not delete the most recent backup
+                options.protected.borrow_mut().clear();
+                if let Some(newest) = options.store(world).newest().await? { options.protected.borrow_mut().insert(newest.timestamp); }
+                compress_all(&options, world).await?;
+            }
+            Ok(())
+        }
+        Some(Subcommand::Dedup { world }) => {
+            let world = S::new(world.unwrap_or(config.default_world));
+            let _lock = if dry_run { None } else { Some(Lock::acquire(&options.backup_path, &world)?) };
+            dedup(&options, &world).await
+        }
+        Some(Subcommand::Diff { old, new, world }) => {
+            let world = S::new(world.unwrap_or(config.default_world));
+            let store = options.store(&world);
+            for (change, path) in diff(&store, store.find_prefix(&old).await?.timestamp, store.find_prefix(&new).await?.timestamp).await? {
+                println!("{change}\t{}", path.display());
+            }
+            Ok(())
+        }
+        Some(Subcommand::Export { checksum, force, timestamp, output, world }) => {
+            let world = S::new(world.unwrap_or(config.default_world));
+            let _lock = if dry_run { None } else { Some(Lock::acquire(&options.backup_path, &world)?) };
+            let backup = options.store(&world).find_prefix(&timestamp).await?;
+            if !force && fs::exists(&output).await? { return Err(Error::Overwrite(output)) }
+            if let Some(checksum) = export(&options, &backup.path, backup.compression, &output, checksum).await? {
+                // same format as the output of `sha256sum`
+                println!("{checksum}  {}", output.display());
+            }
+            Ok(())
+        }
+        Some(Subcommand::Import { version, timestamp, archive, world }) => {
+            let world = S::new(world.unwrap_or(config.default_world));
+            let _lock = if dry_run { None } else { Some(Lock::acquire(&options.backup_path, &world)?) };
+            import(&options, &world, &env::current_dir()?.join(archive), options.timestamp_format.parse(&timestamp)?, &version).await
+        }
+        Some(Subcommand::List { since, until, world }) => {
+            let world = S::new(world.unwrap_or(config.default_world));
+            let backups = list(&options.store(&world), TimeRange::new(&options.timestamp_format, since.as_deref(), until.as_deref())?).await?;
+            let timestamps = backups.iter().map(|listed| options.timestamp_format.display(listed.backup.timestamp).to_string()).collect_vec();
+            // the width of the timestamps depends on the configured format
+            let timestamp_width = timestamps.iter().map(String::len).chain(iter::once("timestamp".len())).max().unwrap();
+            let version_width = backups.iter().map(|listed| listed.backup.version.len()).chain(iter::once("version".len())).max().unwrap();
+            println!("{:<timestamp_width$}  {:<version_width$}  {:<12}  {:<10}  {:<6}  {:<8}  host", "timestamp", "version", "compression", "size", "pinned", "files");
+            for (timestamp, ListedBackup { backup: BackupInfo { version, compression, .. }, size, pinned, file_count, hostname }) in timestamps.into_iter().zip(backups) {
+                println!("{timestamp:<timestamp_width$}  {version:<version_width$}  {:<12}  {:<10}  {:<6}  {:<8}  {}", compression.description(), size.to_string(), if pinned { "yes" } else { "no" }, file_count.map_or_else(|| String::from("-"), |file_count| file_count.to_string()), hostname.as_deref().unwrap_or("-"));
+            }
+            Ok(())
+        }
+        Some(Subcommand::Pin { timestamp, world }) => {
+            let world = S::new(world.unwrap_or(config.default_world));
+            let _lock = if dry_run { None } else { Some(Lock::acquire(&options.backup_path, &world)?) };
+            set_pinned(&options, &world, options.store(&world).find_prefix(&timestamp).await?.timestamp, true).await
+        }
+        Some(Subcommand::Prune { keep, since, until, world }) => {
+            let range = TimeRange::new(&options.timestamp_format, since.as_deref(), until.as_deref())?;
+            let world = S::new(world.unwrap_or(config.default_world));
+            let _lock = if dry_run { None } else { Some(Lock::acquire(&options.backup_path, &world)?) };
+            if !dry_run && !yes {
+                // the --dry-run output shows what would be deleted
+                options.dry_run = Some(RefCell::default());
+                prune(&options, &world, keep, range).await?;
+                if !confirm_deletions(&mut options, &world)? { return Ok(()) }
+            }
+            prune(&options, &world, keep, range).await
+        }
+        Some(Subcommand::Restore { force, allow_downgrade, nth, only, target, timestamp, world }) => {
+            let world = S::new(world.unwrap_or(config.default_world));
+            let _lock = if dry_run { None } else { Some(Lock::acquire(&options.backup_path, &world)?) };
+            let store = options.store(&world);
+            let backup = if let Some(timestamp) = timestamp {
+                store.find_prefix(&timestamp).await?
+            } else {
+                let nth = nth.map_or(1, usize::from);
+                store.nth_newest(nth).await?.ok_or_else(|| Error::NotEnoughBackups { world: world.to_string(), nth })?
+            };
+            let BackupInfo { path, version, compression, .. } = backup;
+            if let Some(target) = target {
+                // make sure the target has a parent to extract compressed backups into
+                let target = env::current_dir()?.join(target);
+                if !force && only.is_empty() && fs::exists(&target).await? { return Err(Error::Overwrite(target)) }
+                restore(&options, &world, &target, &path, compression, &only, force).await
+            } else {
+                let world_dir = options.world_dir(&world);
+                // with --only, an existing world is the point, and --force is instead required to overwrite individual files
+                if !force && only.is_empty() && fs::exists(&world_dir).await? { return Err(Error::Overwrite(world_dir)) }
+                if !allow_downgrade { check_downgrade(&options, &world, &version).await? }
+                if !dry_run && !yes {
+                    let Some(filename) = path.file_name() else { panic!("backup at root") };
+                    confirm(&format!("restore {} into {}{}?", filename.to_string_lossy(), world_dir.display(), if fs::exists(&world_dir).await? { if only.is_empty() { ", replacing the existing world" } else { ", overwriting the matching files" } } else { "" }))?;
+                }
+                with_saves_off(&options, &world, None, &Interrupt::new()?, restore(&options, &world, &world_dir, &path, compression, &only, force)).await
+            }
+        }
+        Some(Subcommand::Stats { world }) => {
+            let world = S::new(world.unwrap_or(config.default_world));
+            let stats = stats(&options.store(&world)).await?;
+            match format {
+                Format::Text => {
+                    println!("backups       {} ({} compressed, {} uncompressed)", stats.num_backups, stats.num_compressed, stats.num_uncompressed);
+                    println!("total size    {}", ByteSize::b(stats.total_size));
+                    println!("average size  {}", stats.average_size.map_or_else(|| String::from("-"), |average_size| ByteSize::b(average_size).to_string()));
+                    println!("oldest        {}", stats.oldest.as_deref().unwrap_or("-"));
+                    println!("newest        {}", stats.newest.as_deref().unwrap_or("-"));
+                    println!("versions      {}", stats.versions.join(", "));
+                }
+                Format::Json => println!("{}", serde_json::to_string(&stats)?),
+            }
+            Ok(())
+        }
+        Some(Subcommand::Unpin { timestamp, world }) => {
+            let world = S::new(world.unwrap_or(config.default_world));
+            let _lock = if dry_run { None } else { Some(Lock::acquire(&options.backup_path, &world)?) };
+            set_pinned(&options, &world, options.store(&world).find_prefix(&timestamp).await?.timestamp, false).await
+        }
+        Some(Subcommand::Verify { world, timestamp }) => {
+            let world = S::new(world.unwrap_or(config.default_world));
+            let timestamp = if let Some(timestamp) = timestamp { Some(options.store(&world).find_prefix(&timestamp).await?.timestamp) } else { None };
+            verify(&options, &world, timestamp).await
+        }
     }
 }
 
-#[wheel::main(debug)]
-async fn main(Args { verbose, world }: Args) -> Result<(), Error> {
-    let world = World::new(world);
-    let was_running = world.is_running().await?;
-    if was_running {
-        world.command("save-off").await?;
-        world.command("save-all").await?;
-        sleep(Duration::from_secs(10)).await;
-    }
-    let res = do_backup(verbose, &world).await;
-    if was_running {
-        let save_on_res = world.command("save-on").await.map(|_| ()).map_err(Error::from); // reenable saves even if backup failed
-        res.and(save_on_res)
-    } else {
-        res
+#[cfg(test)]
+mod tests {
+    use {
+        clap::CommandFactory as _,
+        super::Args,
+    };
+
+    /// Catches argument definitions which clap only rejects at runtime, in debug builds.
+    #[test]
+    fn args() {
+        Args::command().debug_assert();
     }
 }
@@ -0,0 +1,46 @@
+use {
+    std::path::{
+        Path,
+        PathBuf,
+    },
+    serde::{
+        Deserialize,
+        Serialize,
+    },
+    wheel::fs,
+    crate::{
+        Error,
+        sidecar_path,
+    },
+};
+
+/// Information about a backup which can't be derived from its filename, stored in a `.meta.json` file next to the backup.
+///
+/// Backups created by older versions of wurstminebackup don't have this file.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Metadata {
+    pub(crate) minecraft_version: String,
+    /// The target of the world's `minecraft_server.jar` symlink.
+    pub(crate) server_jar: PathBuf,
+    /// How long `rsync` took, in seconds.
+    pub(crate) duration_secs: f64,
+    pub(crate) hostname: String,
+    /// The number of files in the backup, not counting directories.
+    pub(crate) file_count: u64,
+    /// The size of the backup before compression in bytes, used to estimate how large future archives will be. Missing for uncompressed backups.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) uncompressed_size: Option<u64>,
+}
+
+impl Metadata {
+    /// Reads the metadata of the given backup, if it has any.
+    pub(crate) async fn read(backup: &Path) -> Result<Option<Self>, Error> {
+        let path = sidecar_path(backup, "meta.json");
+        Ok(if fs::exists(&path).await? { Some(serde_json::from_str(&fs::read_to_string(path).await?)?) } else { None })
+    }
+
+    pub(crate) async fn write(&self, backup: &Path) -> Result<(), Error> {
+        fs::write(sidecar_path(backup, "meta.json"), serde_json::to_vec_pretty(self)?).await?;
+        Ok(())
+    }
+}
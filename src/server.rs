@@ -0,0 +1,85 @@
+use {
+    std::{
+        fmt,
+        future::Future,
+        path::PathBuf,
+    },
+    systemd_minecraft::World,
+};
+#[cfg(test)] use std::cell::RefCell;
+
+/// The parts of a world managed by systemd-minecraft which are used by wurstminebackup, so changes to systemd-minecraft's API only need to be handled here.
+///
+/// All operations are generic over this trait so they can be tested without a running server.
+pub trait Server: fmt::Display {
+    /// The world with the given name.
+    fn new(name: String) -> Self;
+    /// The directory managed by systemd-minecraft which contains the world's files.
+    fn server_dir(&self) -> PathBuf;
+    /// Whether the world's systemd service is running.
+    fn is_active(&self) -> impl Future<Output = Result<bool, systemd_minecraft::Error>>;
+    /// Sends the given command to the server's console, returning its output.
+    fn send(&self, command: &str) -> impl Future<Output = Result<String, systemd_minecraft::Error>>;
+}
+
+impl Server for World {
+    fn new(name: String) -> Self {
+        World::new(name)
+    }
+
+    fn server_dir(&self) -> PathBuf {
+        self.dir()
+    }
+
+    async fn is_active(&self) -> Result<bool, systemd_minecraft::Error> {
+        self.is_running().await
+    }
+
+    async fn send(&self, command: &str) -> Result<String, systemd_minecraft::Error> {
+        self.command(command).await
+    }
+}
+
+/// A world whose files are in an arbitrary directory, with a fake server console which records the commands sent to it.
+#[cfg(test)]
+pub(crate) struct MockServer {
+    pub(crate) name: String,
+    pub(crate) dir: PathBuf,
+    pub(crate) running: bool,
+    pub(crate) commands: RefCell<Vec<String>>,
+}
+
+#[cfg(test)]
+impl MockServer {
+    pub(crate) fn at(name: &str, dir: PathBuf, running: bool) -> Self {
+        Self { name: name.to_owned(), dir, running, commands: RefCell::default() }
+    }
+}
+
+#[cfg(test)]
+impl fmt::Display for MockServer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.name.fmt(f)
+    }
+}
+
+#[cfg(test)]
+impl Server for MockServer {
+    fn new(name: String) -> Self {
+        let dir = std::env::temp_dir().join("wurstminebackup-mock").join(&name);
+        Self { name, dir, running: false, commands: RefCell::default() }
+    }
+
+    fn server_dir(&self) -> PathBuf {
+        self.dir.clone()
+    }
+
+    async fn is_active(&self) -> Result<bool, systemd_minecraft::Error> {
+        Ok(self.running)
+    }
+
+    async fn send(&self, command: &str) -> Result<String, systemd_minecraft::Error> {
+        self.commands.borrow_mut().push(command.to_owned());
+        Ok(if command == "save-all flush" { String::from("Saved the game") } else { String::default() })
+    }
+}
@@ -0,0 +1,105 @@
+use {
+    std::{
+        os::unix::fs::MetadataExt as _,
+        path::{
+            Path,
+            PathBuf,
+        },
+    },
+    tokio::process::Command,
+    wheel::{
+        fs,
+        traits::{
+            AsyncCommandOutputExt as _,
+            IoResultExt as _,
+        },
+    },
+    crate::{
+        Error,
+        Options,
+    },
+};
+
+/// The inode number of the root directory of every btrfs subvolume.
+const BTRFS_SUBVOLUME_INODE: u64 = 256;
+
+/// A file system which can take atomic snapshots of a world directory, selected using `--snapshot`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SnapshotKind {
+    Zfs,
+    Btrfs,
+}
+
+impl SnapshotKind {
+    /// The file system type as reported by `stat --file-system`.
+    pub(crate) fn fs_type(&self) -> &'static str {
+        match self {
+            Self::Zfs => "zfs",
+            Self::Btrfs => "btrfs",
+        }
+    }
+}
+
+enum SnapshotId {
+    /// The full name of the snapshot, like `tank/minecraft@wurstmineberg`.
+    Zfs(String),
+    /// The path of the read-only snapshot subvolume.
+    Btrfs(PathBuf),
+}
+
+/// A read-only snapshot of the file system containing a world directory. It's not destroyed automatically, so [`Snapshot::destroy`] must be called even if the backup fails.
+pub(crate) struct Snapshot {
+    id: SnapshotId,
+    /// The world directory inside the snapshot. This has the same name as the world directory itself, so the backup has the same layout and `--exclude` patterns still apply.
+    pub(crate) world_dir: PathBuf,
+}
+
+impl Snapshot {
+    /// Snapshots the ZFS dataset or btrfs subvolume containing the given world directory. The snapshot is named after the world directory, so only one can exist per world at a time.
+    ///
+    /// Fails with [`Error::SnapshotUnsupported`] if the world directory isn't on a file system of the given kind.
+    pub(crate) async fn create(options: &Options, kind: SnapshotKind, world_dir: &Path) -> Result<Self, Error> {
+        let Some(world_dir_name) = world_dir.file_name() else { panic!("world directory at root") };
+        let output = Command::new("stat").arg("--file-system").arg("--format=%T").arg(world_dir).check("stat").await?;
+        if String::from_utf8_lossy(&output.stdout).trim() != kind.fs_type() { return Err(Error::SnapshotUnsupported { kind, dir: world_dir.to_owned() }) }
+        Ok(match kind {
+            SnapshotKind::Zfs => {
+                let output = Command::new("zfs").arg("list").arg("-H").arg("-o").arg("name,mountpoint").arg(world_dir).check("zfs").await?;
+                let output = String::from_utf8(output.stdout).map_err(|_| Error::Utf8)?;
+                let Some((dataset, mount_point)) = output.trim_end_matches('\n').split_once('\t') else { return Err(Error::SnapshotUnsupported { kind, dir: world_dir.to_owned() }) };
+                let Ok(relative_path) = world_dir.strip_prefix(mount_point) else { return Err(Error::SnapshotUnsupported { kind, dir: world_dir.to_owned() }) };
+                let name = format!("{dataset}@{}", world_dir_name.to_str().ok_or(Error::Utf8)?);
+                options.run(Command::new("zfs").arg("snapshot").arg(&name), "zfs").await?;
+                Self {
+                    world_dir: Path::new(mount_point).join(".zfs").join("snapshot").join(world_dir_name).join(relative_path),
+                    id: SnapshotId::Zfs(name),
+                }
+            }
+            SnapshotKind::Btrfs => {
+                let mut subvolume = world_dir;
+                while tokio::fs::metadata(subvolume).await.at(subvolume)?.ino() != BTRFS_SUBVOLUME_INODE { //TODO wheel
+                    // the root of a btrfs file system is always a subvolume, so this only happens if the file system was unmounted in the meantime
+                    subvolume = subvolume.parent().ok_or_else(|| Error::SnapshotUnsupported { kind, dir: world_dir.to_owned() })?;
+                }
+                let relative_path = world_dir.strip_prefix(subvolume).expect("subvolume is an ancestor of the world directory");
+                // snapshots must be on the same file system, and the subvolume's parent may be on another one
+                let snapshots_dir = subvolume.join(".wurstminebackup-snapshots");
+                let path = snapshots_dir.join(world_dir_name);
+                if options.dry_run.is_none() { fs::create_dir_all(&snapshots_dir).await? }
+                options.run(Command::new("btrfs").arg("subvolume").arg("snapshot").arg("-r").arg(subvolume).arg(&path), "btrfs").await?;
+                Self {
+                    world_dir: path.join(relative_path),
+                    id: SnapshotId::Btrfs(path),
+                }
+            }
+        })
+    }
+
+    pub(crate) async fn destroy(self, options: &Options) -> Result<(), Error> {
+        match self.id {
+            SnapshotId::Zfs(name) => options.run(Command::new("zfs").arg("destroy").arg(name), "zfs").await?,
+            SnapshotId::Btrfs(path) => options.run(Command::new("btrfs").arg("subvolume").arg("delete").arg(path), "btrfs").await?,
+        };
+        Ok(())
+    }
+}
@@ -0,0 +1,721 @@
+use crate::{
+    server::MockServer,
+    *,
+};
+
+#[test]
+fn filename_round_trip() -> Result<(), Error> {
+    let format = TimestampFormat::default();
+    let timestamp = Utc.with_ymd_and_hms(2024, 6, 1, 13, 37, 42).single().expect("valid date");
+    let filename = format!("{}_1.20.4.tar.gz", format.display(timestamp));
+    assert_eq!(filename, "2024-06-01_13-37-42_1.20.4.tar.gz");
+    assert_eq!(format.parse_filename(&filename)?, (timestamp, "1.20.4", Compression::Gzip));
+    // new backups are named using the current time, which is truncated to the precision of the format
+    let now = Utc::now();
+    assert_eq!(format.parse_filename(&format!("{}_24w14a", format.display(now)))?, (now.trunc_subsecs(0), "24w14a", Compression::None));
+    Ok(())
+}
+
+/// Computes the same as [`dir_size`] one entry at a time.
+fn sequential_size(path: &Path) -> Result<u64, Error> {
+    let metadata = std::fs::symlink_metadata(path).at(path)?;
+    if metadata.is_dir() {
+        let mut size = 0;
+        for entry in std::fs::read_dir(path).at(path)? {
+            size += sequential_size(&entry.at(path)?.path())?;
+        }
+        Ok(size)
+    } else {
+        Ok(metadata.len())
+    }
+}
+
+/// Creates a tree of the given depth with several subdirectories and differently sized files at each level.
+fn synthetic_tree(path: &Path, depth: usize) -> Result<(), Error> {
+    std::fs::create_dir_all(path).at(path)?;
+    for idx in 0..3 {
+        std::fs::write(path.join(format!("file{idx}")), vec![0; depth * 100 + idx]).at(path)?;
+        if depth > 0 { synthetic_tree(&path.join(format!("dir{idx}")), depth - 1)? }
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn dir_size_matches_sequential() -> Result<(), Error> {
+    let temp = tempfile::tempdir()?;
+    let root = temp.path().join("tree");
+    synthetic_tree(&root, 6)?;
+    // more entries than are inspected concurrently
+    for idx in 0..DIR_SIZE_CONCURRENCY * 3 {
+        std::fs::write(root.join(format!("wide{idx}")), vec![0; idx]).at(&root)?;
+    }
+    std::os::unix::fs::symlink("/nonexistent/target", root.join("link"))?;
+    assert_eq!(dir_size(&root).await?.as_u64(), sequential_size(&root)?);
+    Ok(())
+}
+
+#[test]
+fn version_from_jar_filename() {
+    assert_eq!(jar_version("minecraft_server.1.20.4.jar"), Some("1.20.4"));
+    assert_eq!(jar_version("minecraft_server.24w14a.jar"), Some("24w14a"));
+    // the default name, which is usually a symlink to a versioned jar
+    assert_eq!(jar_version("minecraft_server.jar"), None);
+    assert_eq!(jar_version("minecraft_server..jar"), None);
+    assert_eq!(jar_version("paper-1.20.4.jar"), None);
+}
+
+/// Options for backing up to the given backup path with all optional behavior disabled.
+fn options(backup_path: PathBuf) -> Options {
+    Options {
+        backup_path,
+        layout: Layout::PerWorld,
+        timestamp_format: TimestampFormat::default(),
+        remote: None,
+        compression: Compression::None,
+        worlds: HashMap::default(),
+        world_dir: None,
+        jar_path: None,
+        excludes: Arc::default(),
+        skip_dimensions: Vec::default(),
+        incremental: false,
+        stream_compress: false,
+        compress_jobs: 1,
+        keep_uncompressed: 0,
+        compress_before_delete: false,
+        compress_program: None,
+        compression_level: None,
+        force: false,
+        min_interval: None,
+        clean_partial: false,
+        timestamp: None,
+        checksum: false,
+        verify_compressed: false,
+        command_timeout: None,
+        sync: false,
+        toggle_saves: true,
+        server_check: false,
+        snapshot: None,
+        snapshot_dir: RefCell::default(),
+        max_rsync_passes: 3,
+        nice: 0,
+        ionice: IoPriority::None,
+        bwlimit: None,
+        min_free_bytes: ByteSize::default(),
+        min_free_percent: 0.0,
+        max_disk_usage_percent: None,
+        dry_run: None,
+        deleted: RefCell::default(),
+        retention_strategy: RetentionStrategy::default(),
+        keep_last: 0,
+        protected: RefCell::default(),
+        audit_log: None,
+    }
+}
+
+/// Sets the modification time of the given path and everything inside it, like a world which was last saved at that time.
+fn set_modified(path: &Path, time: SystemTime) -> Result<(), Error> {
+    if path.is_dir() {
+        for entry in std::fs::read_dir(path).at(path)? {
+            set_modified(&entry.at(path)?.path(), time)?;
+        }
+    }
+    std::fs::File::open(path).at(path)?.set_modified(time).at(path)?;
+    Ok(())
+}
+
+/// Creates a minimal world named `wurstmineberg` running Minecraft 1.20.4 in the given directory, last modified an hour ago.
+async fn mock_world(root: &Path, running: bool) -> Result<MockServer, Error> {
+    let dir = root.join("worlds").join("wurstmineberg");
+    fs::create_dir_all(dir.join("region")).await?;
+    fs::write(dir.join("level.dat"), b"level").await?;
+    fs::write(dir.join("region").join("r.0.0.mca"), vec![0; 4096]).await?;
+    fs::write(dir.join("minecraft_server.jar"), b"jar").await?;
+    fs::write(dir.join("version.json"), br#"{"id": "1.20.4"}"#).await?;
+    set_modified(&dir, SystemTime::now() - Duration::from_secs(60 * 60))?;
+    Ok(MockServer::at("wurstmineberg", dir, running))
+}
+
+#[tokio::test]
+async fn backup_mock_server() -> Result<(), Error> {
+    let temp = tempfile::tempdir()?;
+    let world = mock_world(temp.path(), true).await?;
+    let options = options(temp.path().join("backup"));
+    fs::create_dir_all(options.store(&world).dir()).await?;
+    let summary = with_saves_off(&options, &world, None, &Interrupt::new()?, do_backup(&options, &world, ByteSize::default())).await?;
+    assert_eq!(summary.version.as_deref(), Some("1.20.4"));
+    assert_eq!(summary.skipped, None);
+    assert_eq!(*world.commands.borrow(), ["save-off", "save-on"]);
+    let backups = options.store(&world).scan().await?.0;
+    assert_eq!(backups.len(), 1);
+    assert_eq!(Some(backups[0].path.file_name().unwrap().to_str().unwrap()), summary.backup.as_deref());
+    assert!(fs::exists(backups[0].path.join("wurstmineberg").join("level.dat")).await?);
+    // the world hasn't changed since
+    let summary = do_backup(&options, &world, ByteSize::default()).await?;
+    assert_eq!(summary.backup, None);
+    assert_eq!(summary.skipped, Some(SkipReason::Unchanged));
+    assert_eq!(options.store(&world).scan().await?.0.len(), 1);
+    // the newest backup was just created
+    let mut options = options;
+    options.min_interval = Some(chrono::Duration::hours(1));
+    let summary = prepare_backup(&options, &world).await?.expect("backup skipped");
+    assert_eq!(summary.skipped, Some(SkipReason::MinInterval));
+    Ok(())
+}
+
+#[tokio::test]
+async fn latest_modification_ignores_excludes() -> Result<(), Error> {
+    let temp = tempfile::tempdir()?;
+    mock_world(temp.path(), true).await?;
+    let dir = temp.path().join("worlds").join("wurstmineberg");
+    let saved = SystemTime::now() - Duration::from_secs(60 * 60);
+    fs::write(dir.join("session.lock"), b"lock").await?;
+    set_modified(&dir, saved)?;
+    set_modified(&dir.join("session.lock"), SystemTime::now())?;
+    let excludes = Arc::new(Excludes::new(vec![String::from("session.lock")])?);
+    assert_eq!(latest_modification(&dir, excludes, PathBuf::from("wurstmineberg")).await?, saved);
+    assert!(latest_modification(&dir, Arc::default(), PathBuf::from("wurstmineberg")).await? > saved);
+    Ok(())
+}
+
+#[tokio::test]
+async fn retention_keeps_only_the_new_backup() -> Result<(), Error> {
+    let temp = tempfile::tempdir()?;
+    let world = mock_world(temp.path(), false).await?;
+    let mut options = options(temp.path().join("backup"));
+    options.toggle_saves = false;
+    options.worlds.insert(world.name.clone(), WorldConfig { max_backups: Some(1), max_total_size: None });
+    synthetic_backup(&options, &world, "2024-01-01_00-00-00", "1.20.4", 1024).await?;
+    synthetic_backup(&options, &world, "2024-01-02_00-00-00", "1.20.4", 1024).await?;
+    // the world was modified an hour ago, long after these backups
+    let summary = do_backup(&options, &world, ByteSize::default()).await?;
+    assert_eq!(filenames(&options, &world).await?, [summary.backup.expect("backup created")]);
+    Ok(())
+}
+
+fn timestamp(timestamp: &str) -> DateTime<Utc> {
+    TimestampFormat::default().parse(timestamp).expect("valid timestamp")
+}
+
+/// Creates an uncompressed backup of the given world containing a region file of the given size.
+async fn synthetic_backup(options: &Options, world: &MockServer, timestamp: &str, version: &str, size: usize) -> Result<PathBuf, Error> {
+    let path = options.store(world).dir().join(format!("{timestamp}_{version}"));
+    let backed_up_world = path.join(&world.name);
+    fs::create_dir_all(backed_up_world.join("region")).await?;
+    fs::write(backed_up_world.join("level.dat"), b"level").await?;
+    fs::write(backed_up_world.join("region").join("r.0.0.mca"), vec![0; size]).await?;
+    Ok(path)
+}
+
+/// The filenames of the given world's backups, oldest first.
+async fn filenames(options: &Options, world: &MockServer) -> Result<Vec<String>, Error> {
+    Ok(options.store(world).list().await?.into_iter().map(|backup| backup.filename).collect())
+}
+
+#[tokio::test]
+async fn delete_closest_in_order() -> Result<(), Error> {
+    let temp = tempfile::tempdir()?;
+    let world = mock_world(temp.path(), false).await?;
+    let options = options(temp.path().join("backup"));
+    for timestamp in ["2024-01-01_00-00-00", "2024-01-01_06-00-00", "2024-01-01_06-10-00", "2024-01-01_12-00-00"] {
+        synthetic_backup(&options, &world, timestamp, "1.20.4", 1024).await?;
+    }
+    let store = options.store(&world);
+    assert!(store.delete_closest(&options).await?);
+    assert_eq!(filenames(&options, &world).await?, ["2024-01-01_00-00-00_1.20.4", "2024-01-01_06-00-00_1.20.4", "2024-01-01_12-00-00_1.20.4"]);
+    assert!(store.delete_closest(&options).await?);
+    assert_eq!(filenames(&options, &world).await?, ["2024-01-01_00-00-00_1.20.4", "2024-01-01_12-00-00_1.20.4"]);
+    // with only two backups left, the older one goes first
+    assert!(store.delete_closest(&options).await?);
+    assert_eq!(filenames(&options, &world).await?, ["2024-01-01_12-00-00_1.20.4"]);
+    assert!(!store.delete_closest(&options).await?);
+    assert_eq!(*options.deleted.borrow(), ["2024-01-01_06-10-00_1.20.4", "2024-01-01_06-00-00_1.20.4", "2024-01-01_00-00-00_1.20.4"]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn scan_and_delete_per_layout() -> Result<(), Error> {
+    for layout in [Layout::PerWorld, Layout::Flat] {
+        let temp = tempfile::tempdir()?;
+        let world = mock_world(temp.path(), false).await?;
+        let mut options = options(temp.path().join("backup"));
+        options.layout = layout;
+        let dir = options.store(&world).dir();
+        let rotated_audit_log = format!("{AUDIT_LOG_FILENAME}.1");
+        assert_eq!(dir, match layout {
+            Layout::PerWorld => temp.path().join("backup").join("wurstmineberg"),
+            Layout::Flat => temp.path().join("backup"),
+        });
+        for hour in 0..2 {
+            synthetic_backup(&options, &world, &format!("2024-01-01_{hour:02}-00-00"), "1.20.4", 1024).await?;
+        }
+        fs::write(dir.join("2024-01-01_00-00-00_1.20.4.pinned"), b"").await?;
+        fs::create_dir_all(dir.join(".2024-01-01_02-00-00_1.20.4.tmp")).await?;
+        // kept directly in the backup path, so they're next to the backups with the flat layout
+        for auxiliary in ["wurstmineberg.lock", AUDIT_LOG_FILENAME, &*rotated_audit_log] {
+            fs::write(temp.path().join("backup").join(auxiliary), b"").await?;
+        }
+        let store = options.store(&world);
+        let (backups, invalid) = store.scan().await?;
+        assert_eq!(backups.iter().map(|backup| &*backup.filename).collect_vec(), ["2024-01-01_00-00-00_1.20.4", "2024-01-01_01-00-00_1.20.4"], "{layout:?}");
+        assert!(invalid.is_empty(), "{layout:?}");
+        // only auxiliary with the flat layout
+        fs::write(dir.join("level.lock"), b"").await?;
+        fs::write(temp.path().join("backup").join("level.lock"), b"").await?;
+        assert_eq!(store.scan().await?.1.len(), match layout {
+            Layout::PerWorld => 1,
+            Layout::Flat => 0,
+        }, "{layout:?}");
+        fs::remove_file(dir.join("level.lock")).await?;
+        delete_backup(&options, &store, String::from("2024-01-01_00-00-00_1.20.4")).await?;
+        assert_eq!(store.scan().await?.0.into_iter().map(|backup| backup.filename).collect_vec(), ["2024-01-01_01-00-00_1.20.4"], "{layout:?}");
+        assert!(!fs::exists(dir.join("2024-01-01_00-00-00_1.20.4.pinned")).await?);
+        assert!(fs::exists(dir.join(".2024-01-01_02-00-00_1.20.4.tmp")).await?);
+        for auxiliary in ["wurstmineberg.lock", AUDIT_LOG_FILENAME, &*rotated_audit_log] {
+            assert!(fs::exists(temp.path().join("backup").join(auxiliary)).await?, "{layout:?}: {auxiliary}");
+        }
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn delete_symlinked_backup() -> Result<(), Error> {
+    let temp = tempfile::tempdir()?;
+    let world = mock_world(temp.path(), false).await?;
+    let options = options(temp.path().join("backup"));
+    synthetic_backup(&options, &world, "2024-01-01_12-00-00", "1.20.4", 1024).await?;
+    // e.g. moved to an archive disk
+    let target = temp.path().join("archive").join("2024-01-01_00-00-00_1.20.4");
+    fs::create_dir_all(target.join("wurstmineberg")).await?;
+    fs::write(target.join("wurstmineberg").join("level.dat"), b"level").await?;
+    let link = options.store(&world).dir().join("2024-01-01_00-00-00_1.20.4");
+    std::os::unix::fs::symlink(&target, &link)?;
+    fs::write(sidecar_path(&link, "pinned"), b"").await?;
+    assert_eq!(filenames(&options, &world).await?, ["2024-01-01_00-00-00_1.20.4", "2024-01-01_12-00-00_1.20.4"]);
+    delete_backup(&options, &options.store(&world), String::from("2024-01-01_00-00-00_1.20.4")).await?;
+    assert_eq!(filenames(&options, &world).await?, ["2024-01-01_12-00-00_1.20.4"]);
+    assert!(fs::symlink_metadata(&link).await.is_err());
+    assert!(!fs::exists(sidecar_path(&link, "pinned")).await?);
+    assert_eq!(fs::read_to_string(target.join("wurstmineberg").join("level.dat")).await?, "level");
+    Ok(())
+}
+
+#[tokio::test]
+async fn make_room_until_full() -> Result<(), Error> {
+    let temp = tempfile::tempdir()?;
+    let world = mock_world(temp.path(), false).await?;
+    let mut options = options(temp.path().join("backup"));
+    for hour in 0..4 {
+        synthetic_backup(&options, &world, &format!("2024-01-01_{hour:02}-00-00"), "1.20.4", 1024).await?;
+    }
+    // there's room without deleting anything
+    assert!(make_room(&options, ByteSize::kb(1), &world).await?);
+    assert_eq!(filenames(&options, &world).await?.len(), 4);
+    // there's never room, so everything but the last remaining backup is deleted
+    options.min_free_percent = 100.0;
+    assert!(!make_room(&options, ByteSize::kb(1), &world).await?);
+    assert_eq!(options.deleted.borrow().len(), 3);
+    assert_eq!(filenames(&options, &world).await?, ["2024-01-01_03-00-00_1.20.4"]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn compress_all_but_newest() -> Result<(), Error> {
+    let temp = tempfile::tempdir()?;
+    let world = mock_world(temp.path(), false).await?;
+    let mut options = options(temp.path().join("backup"));
+    options.compression = Compression::Gzip;
+    options.keep_uncompressed = 1;
+    options.verify_compressed = true;
+    for (hour, size) in [(0, 4096), (1, 1024), (2, 2048)] {
+        synthetic_backup(&options, &world, &format!("2024-01-01_{hour:02}-00-00"), "1.20.4", size).await?;
+    }
+    compress_all(&options, &world).await?;
+    let backups = options.store(&world).list().await?;
+    assert_eq!(backups.iter().map(|backup| (&*backup.filename, backup.compression)).collect_vec(), [
+        ("2024-01-01_00-00-00_1.20.4.tar.gz", Compression::Gzip),
+        ("2024-01-01_01-00-00_1.20.4.tar.gz", Compression::Gzip),
+        ("2024-01-01_02-00-00_1.20.4", Compression::None),
+    ]);
+    // the zeroed region files compress well
+    assert!(dir_size(&backups[0].path).await? < ByteSize::b(4096));
+    assert!(!fs::exists(options.store(&world).dir().join("2024-01-01_00-00-00_1.20.4")).await?);
+    Ok(())
+}
+
+#[tokio::test]
+async fn retention_keeps_max_backups() -> Result<(), Error> {
+    let temp = tempfile::tempdir()?;
+    let world = mock_world(temp.path(), false).await?;
+    let options = options(temp.path().join("backup"));
+    for timestamp in ["2024-01-01_00-00-00", "2024-01-02_00-00-00", "2024-01-03_00-00-00"] {
+        synthetic_backup(&options, &world, timestamp, "1.20.4", 1024).await?;
+    }
+    enforce_retention(&options, &world, Some(1), None).await?;
+    assert_eq!(filenames(&options, &world).await?, ["2024-01-03_00-00-00_1.20.4"]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn keep_uncompressed_newest() -> Result<(), Error> {
+    for keep_uncompressed in [0, 2, 5] {
+        let temp = tempfile::tempdir()?;
+        let world = mock_world(temp.path(), false).await?;
+        let mut options = options(temp.path().join("backup"));
+        options.compression = Compression::Gzip;
+        options.keep_uncompressed = keep_uncompressed;
+        for hour in 0..4 {
+            synthetic_backup(&options, &world, &format!("2024-01-01_{hour:02}-00-00"), "1.20.4", 1024).await?;
+        }
+        // compressing again doesn't touch the backups which were kept uncompressed
+        for _ in 0..2 {
+            compress_all(&options, &world).await?;
+            let filenames = filenames(&options, &world).await?;
+            assert_eq!(filenames.len(), 4);
+            let num_uncompressed = keep_uncompressed.min(4);
+            assert!(filenames[..4 - num_uncompressed].iter().all(|filename| filename.ends_with(".tar.gz")), "keep_uncompressed = {keep_uncompressed}");
+            assert!(filenames[4 - num_uncompressed..].iter().all(|filename| !filename.ends_with(".tar.gz")), "keep_uncompressed = {keep_uncompressed}");
+        }
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn compress_same_backup_twice() -> Result<(), Error> {
+    let temp = tempfile::tempdir()?;
+    let world = mock_world(temp.path(), false).await?;
+    let members = ["data/raids.dat", "level.dat", "region/r.0.0.mca", "region/r.-1.0.mca", "region/r.0.-1.mca"];
+    let mut checksums = Vec::default();
+    for (backup_path, reverse) in [("first", false), ("second", true)] {
+        let mut options = options(temp.path().join(backup_path));
+        options.compression = Compression::Gzip;
+        // gzip's header would otherwise include a timestamp
+        options.compress_program = Some(String::from("gzip -n"));
+        // the same backup name, since it's part of the archive's members
+        let path = options.store(&world).dir().join("2024-01-01_00-00-00_1.20.4");
+        // created in a different order, so the directory order is likely to differ
+        let members = if reverse { members.iter().rev().collect_vec() } else { members.iter().collect_vec() };
+        for member in members {
+            let member_path = path.join("wurstmineberg").join(member);
+            fs::create_dir_all(member_path.parent().unwrap()).await?;
+            fs::write(&member_path, member.as_bytes()).await?;
+        }
+        set_modified(&path, SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000))?;
+        compress_one(&options, &world, path.clone(), dir_size(&path).await?).await?;
+        checksums.push(sha256(&sidecar_path(&path, "tar.gz")).await?);
+    }
+    assert_eq!(checksums[0], checksums[1]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn dedup_marks_unchanged_archives() -> Result<(), Error> {
+    let temp = tempfile::tempdir()?;
+    let world = mock_world(temp.path(), false).await?;
+    let mut options = options(temp.path().join("backup"));
+    options.compression = Compression::Gzip;
+    options.retention_strategy = RetentionStrategy::TimeFirst;
+    for (hour, size) in [(0, 1024), (11, 1024), (12, 2048), (13, 4096)] {
+        let path = synthetic_backup(&options, &world, &format!("2024-01-01_{hour:02}-00-00"), "1.20.4", size).await?;
+        // except for the backup folder itself, as if the world didn't change between the first two backups
+        set_modified(&path.join("wurstmineberg"), SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000))?;
+    }
+    compress_all(&options, &world).await?;
+    dedup(&options, &world).await?;
+    let dir = options.store(&world).dir();
+    assert_eq!(duplicate_of(&dir.join("2024-01-01_11-00-00_1.20.4.tar.gz")).await?.as_deref(), Some("2024-01-01_00-00-00_1.20.4.tar.gz"));
+    for filename in ["2024-01-01_00-00-00_1.20.4.tar.gz", "2024-01-01_12-00-00_1.20.4.tar.gz", "2024-01-01_13-00-00_1.20.4.tar.gz"] {
+        assert_eq!(duplicate_of(&dir.join(filename)).await?, None, "{filename}");
+    }
+    // the 12:00 backup is closer to its neighbors, but the duplicate goes first
+    assert!(options.store(&world).delete_closest(&options).await?);
+    assert_eq!(filenames(&options, &world).await?, ["2024-01-01_00-00-00_1.20.4.tar.gz", "2024-01-01_12-00-00_1.20.4.tar.gz", "2024-01-01_13-00-00_1.20.4.tar.gz"]);
+    assert!(!fs::exists(dir.join("2024-01-01_11-00-00_1.20.4.tar.gz.duplicate")).await?);
+    Ok(())
+}
+
+#[tokio::test]
+async fn list_in_range() -> Result<(), Error> {
+    let temp = tempfile::tempdir()?;
+    let world = mock_world(temp.path(), false).await?;
+    let options = options(temp.path().join("backup"));
+    for (day, size) in [(1, 1024), (2, 2048), (3, 4096)] {
+        synthetic_backup(&options, &world, &format!("2024-01-{day:02}_12-00-00"), "1.20.4", size).await?;
+    }
+    fs::write(options.store(&world).dir().join("notes.txt"), b"not a backup").await?;
+    set_pinned(&options, &world, timestamp("2024-01-02_12-00-00"), true).await?;
+    let listed = list(&options.store(&world), TimeRange::new(&options.timestamp_format, Some("2024-01-02"), None)?).await?;
+    assert_eq!(listed.iter().map(|listed| (listed.backup.timestamp, listed.pinned, listed.file_count)).collect_vec(), [
+        (timestamp("2024-01-02_12-00-00"), true, None),
+        (timestamp("2024-01-03_12-00-00"), false, None),
+    ]);
+    // directories count towards the size, so it's at least the size of the region file
+    assert!(listed[0].size >= ByteSize::b(2048));
+    assert_eq!(listed[1].size.as_u64() - listed[0].size.as_u64(), 2048);
+    Ok(())
+}
+
+/// Entries for [`choose_victim`] from timestamps and versions. The filenames are the backup names.
+fn entries(backups: &[(&str, &str)]) -> Vec<(Version, DateTime<Utc>, String)> {
+    backups.iter().map(|&(ts, version)| (Version::parse(version), timestamp(ts), format!("{ts}_{version}"))).collect()
+}
+
+#[test]
+fn parse_versions() {
+    assert_eq!(Version::parse("1.20.4"), Version::Release([1, 20, 4]));
+    assert_eq!(Version::parse("1.21"), Version::Release([1, 21, 0]));
+    assert_eq!(Version::parse("24w14a"), Version::Snapshot);
+    assert_eq!(Version::parse("1.20.5-pre1"), Version::Snapshot);
+    assert_eq!(Version::parse("1.20.5-rc1"), Version::Snapshot);
+}
+
+#[test]
+fn victim_among_snapshots() {
+    // snapshots count as the preceding release, so the only snapshot closer to other backups of its release than to the next release is deleted
+    let backups = entries(&[("2024-04-01_00-00-00", "1.20.4"), ("2024-04-01_01-00-00", "24w14a"), ("2024-04-01_02-00-00", "24w15a"), ("2024-04-01_03-00-00", "1.20.5")]);
+    assert_eq!(choose_victim(RetentionStrategy::VersionFirst, &backups, |_| false, |_| false), Some("2024-04-01_01-00-00_24w14a"));
+    // within a release, snapshots are only compared by time, so the closer backup of the release itself is deleted
+    let backups = entries(&[("2024-04-01_00-00-00", "1.20.4"), ("2024-04-01_01-00-00", "1.20.4"), ("2024-04-01_10-00-00", "24w14a"), ("2024-04-01_20-00-00", "1.20.5")]);
+    assert_eq!(choose_victim(RetentionStrategy::VersionFirst, &backups, |_| false, |_| false), Some("2024-04-01_01-00-00_1.20.4"));
+    // without a preceding release, a snapshot sorts before all releases
+    let backups = entries(&[("2024-04-01_00-00-00", "24w14a"), ("2024-04-01_01-00-00", "1.20.5"), ("2024-04-01_02-00-00", "1.20.5"), ("2024-04-01_03-00-00", "1.20.5")]);
+    assert_eq!(choose_victim(RetentionStrategy::VersionFirst, &backups, |_| false, |_| false), Some("2024-04-01_02-00-00_1.20.5"));
+    // versions don't matter at all with --retention-strategy=time-first
+    let backups = entries(&[("2024-04-01_00-00-00", "1.20.4"), ("2024-04-01_01-00-00", "24w14a"), ("2024-04-01_05-00-00", "1.20.5"), ("2024-04-01_07-00-00", "1.20.5")]);
+    assert_eq!(choose_victim(RetentionStrategy::TimeFirst, &backups, |_| false, |_| false), Some("2024-04-01_01-00-00_24w14a"));
+}
+
+#[test]
+fn choose_victim_table() {
+    let cases: &[(&str, RetentionStrategy, &[(&str, &str)], &[&str], Option<&str>)] = &[
+        ("two backups", RetentionStrategy::VersionFirst, &[("2024-01-01_00-00-00", "1.20.4"), ("2024-01-02_00-00-00", "1.20.4")], &[], Some("2024-01-01_00-00-00_1.20.4")),
+        ("two backups", RetentionStrategy::TimeFirst, &[("2024-01-01_00-00-00", "1.20.4"), ("2024-01-02_00-00-00", "1.20.4")], &[], Some("2024-01-01_00-00-00_1.20.4")),
+        // ties go to the oldest
+        ("evenly spaced", RetentionStrategy::VersionFirst, &[("2024-01-01_00-00-00", "1.20.4"), ("2024-01-01_06-00-00", "1.20.4"), ("2024-01-01_12-00-00", "1.20.4"), ("2024-01-01_18-00-00", "1.20.4")], &[], Some("2024-01-01_06-00-00_1.20.4")),
+        ("evenly spaced", RetentionStrategy::TimeFirst, &[("2024-01-01_00-00-00", "1.20.4"), ("2024-01-01_06-00-00", "1.20.4"), ("2024-01-01_12-00-00", "1.20.4"), ("2024-01-01_18-00-00", "1.20.4")], &[], Some("2024-01-01_06-00-00_1.20.4")),
+        // of the two backups closest to each other, the one closer to its other neighbor goes
+        ("clustered pair", RetentionStrategy::TimeFirst, &[("2024-01-01_00-00-00", "1.20.4"), ("2024-01-01_06-00-00", "1.20.4"), ("2024-01-01_06-10-00", "1.20.4"), ("2024-01-01_12-00-00", "1.20.4"), ("2024-01-01_18-00-00", "1.20.4")], &[], Some("2024-01-01_06-10-00_1.20.4")),
+        ("mixed versions", RetentionStrategy::VersionFirst, &[("2024-01-01_00-00-00", "1.20.4"), ("2024-01-01_10-00-00", "1.20.4"), ("2024-01-01_11-00-00", "1.20.5"), ("2024-01-01_20-00-00", "1.20.5"), ("2024-01-01_21-00-00", "1.20.5")], &[], Some("2024-01-01_20-00-00_1.20.5")),
+        ("mixed versions", RetentionStrategy::TimeFirst, &[("2024-01-01_00-00-00", "1.20.4"), ("2024-01-01_10-00-00", "1.20.4"), ("2024-01-01_11-00-00", "1.20.5"), ("2024-01-01_20-00-00", "1.20.5"), ("2024-01-01_21-00-00", "1.20.5")], &[], Some("2024-01-01_11-00-00_1.20.5")),
+        ("protected", RetentionStrategy::TimeFirst, &[("2024-01-01_00-00-00", "1.20.4"), ("2024-01-01_06-00-00", "1.20.4"), ("2024-01-01_12-00-00", "1.20.4"), ("2024-01-01_18-00-00", "1.20.4")], &["2024-01-01_06-00-00"], Some("2024-01-01_12-00-00_1.20.4")),
+        // with only the first and last unprotected, the older one goes
+        ("protected middle", RetentionStrategy::VersionFirst, &[("2024-01-01_00-00-00", "1.20.4"), ("2024-01-01_06-00-00", "1.20.4"), ("2024-01-01_12-00-00", "1.20.4"), ("2024-01-01_18-00-00", "1.20.4")], &["2024-01-01_06-00-00", "2024-01-01_12-00-00"], Some("2024-01-01_00-00-00_1.20.4")),
+        ("protected older of two", RetentionStrategy::TimeFirst, &[("2024-01-01_00-00-00", "1.20.4"), ("2024-01-02_00-00-00", "1.20.4")], &["2024-01-01_00-00-00"], Some("2024-01-02_00-00-00_1.20.4")),
+        // like --keep-last=2
+        ("keep last", RetentionStrategy::TimeFirst, &[("2024-01-01_00-00-00", "1.20.4"), ("2024-01-01_06-00-00", "1.20.4"), ("2024-01-01_12-00-00", "1.20.4"), ("2024-01-01_13-00-00", "1.20.4")], &["2024-01-01_12-00-00", "2024-01-01_13-00-00"], Some("2024-01-01_06-00-00_1.20.4")),
+        ("keep last", RetentionStrategy::Oldest, &[("2024-01-01_00-00-00", "1.20.4"), ("2024-01-01_06-00-00", "1.20.4"), ("2024-01-01_12-00-00", "1.20.4")], &["2024-01-01_06-00-00", "2024-01-01_12-00-00"], Some("2024-01-01_00-00-00_1.20.4")),
+        ("everything protected", RetentionStrategy::VersionFirst, &[("2024-01-01_00-00-00", "1.20.4"), ("2024-01-01_06-00-00", "1.20.4")], &["2024-01-01_00-00-00", "2024-01-01_06-00-00"], None),
+        ("single backup", RetentionStrategy::Oldest, &[("2024-01-01_00-00-00", "1.20.4")], &[], None),
+    ];
+    for &(description, strategy, backups, protected, expected) in cases {
+        let backups = entries(backups);
+        let protected = protected.iter().map(|&ts| timestamp(ts)).collect::<HashSet<_>>();
+        assert_eq!(choose_victim(strategy, &backups, |timestamp| protected.contains(&timestamp), |_| false), expected, "{description} ({strategy:?})");
+    }
+}
+
+#[test]
+fn duplicates_first() {
+    let backups = entries(&[("2024-01-01_00-00-00", "1.20.4"), ("2024-01-01_11-00-00", "1.20.4"), ("2024-01-01_12-00-00", "1.20.4"), ("2024-01-01_13-00-00", "1.20.4")]);
+    for (strategy, usual) in [
+        (RetentionStrategy::VersionFirst, "2024-01-01_12-00-00_1.20.4"),
+        (RetentionStrategy::TimeFirst, "2024-01-01_12-00-00_1.20.4"),
+        (RetentionStrategy::Oldest, "2024-01-01_00-00-00_1.20.4"),
+        (RetentionStrategy::Newest, "2024-01-01_13-00-00_1.20.4"),
+    ] {
+        assert_eq!(choose_victim(strategy, &backups, |_| false, |_| false), Some(usual), "{strategy:?}");
+        // including the newest backup, which is never chosen by distance
+        for duplicate in ["2024-01-01_11-00-00", "2024-01-01_13-00-00"] {
+            let filename = format!("{duplicate}_1.20.4");
+            let duplicate = timestamp(duplicate);
+            assert_eq!(choose_victim(strategy, &backups, |_| false, |timestamp| timestamp == duplicate), Some(&*filename), "{strategy:?}");
+            // protected duplicates are kept like any other protected backup
+            assert_eq!(choose_victim(strategy, &backups, |timestamp| timestamp == duplicate, |timestamp| timestamp == duplicate), choose_victim(strategy, &backups, |timestamp| timestamp == duplicate, |_| false), "{strategy:?}");
+        }
+    }
+}
+
+/// Candidates for [`RetentionStrategy::victim`] with the given release, timestamp, and whether they're protected. The filenames are the timestamps.
+fn candidates<'a>(backups: &[([i64; 3], &'a str, bool)]) -> Vec<Candidate<'a>> {
+    backups.iter().map(|&(release, ts, protected)| Candidate { release, timestamp: timestamp(ts), filename: ts, protected, duplicate: false }).collect()
+}
+
+#[test]
+fn victim_per_strategy() {
+    let backups = candidates(&[
+        ([1, 20, 4], "2024-01-01_00-00-00", false),
+        ([1, 20, 4], "2024-01-01_01-00-00", false),
+        ([1, 20, 4], "2024-01-01_01-10-00", false),
+        ([1, 20, 5], "2024-01-01_01-20-00", false),
+        ([1, 20, 5], "2024-01-01_12-00-00", false),
+    ]);
+    let victim = |strategy: RetentionStrategy, backups: &[Candidate<'_>]| strategy.victim(backups).map(|candidate| candidate.filename.to_owned());
+    // the last backup of 1.20.4 is close in time to the first of 1.20.5, but a different release is farther away than any amount of time
+    assert_eq!(victim(RetentionStrategy::VersionFirst, &backups).as_deref(), Some("2024-01-01_01-00-00"));
+    assert_eq!(victim(RetentionStrategy::TimeFirst, &backups).as_deref(), Some("2024-01-01_01-10-00"));
+    assert_eq!(victim(RetentionStrategy::Oldest, &backups).as_deref(), Some("2024-01-01_00-00-00"));
+    assert_eq!(victim(RetentionStrategy::Newest, &backups).as_deref(), Some("2024-01-01_12-00-00"));
+    // protected backups are skipped by every strategy
+    let backups = candidates(&[
+        ([1, 20, 4], "2024-01-01_00-00-00", true),
+        ([1, 20, 4], "2024-01-01_01-00-00", true),
+        ([1, 20, 4], "2024-01-01_01-10-00", true),
+        ([1, 20, 5], "2024-01-01_01-20-00", false),
+        ([1, 20, 5], "2024-01-01_12-00-00", true),
+    ]);
+    for strategy in [RetentionStrategy::VersionFirst, RetentionStrategy::TimeFirst, RetentionStrategy::Oldest, RetentionStrategy::Newest] {
+        assert_eq!(victim(strategy, &backups).as_deref(), Some("2024-01-01_01-20-00"), "{strategy:?}");
+    }
+    // the last remaining backup is never deleted, even if it's not protected
+    let backups = candidates(&[([1, 20, 4], "2024-01-01_00-00-00", false)]);
+    for strategy in [RetentionStrategy::VersionFirst, RetentionStrategy::TimeFirst, RetentionStrategy::Oldest, RetentionStrategy::Newest] {
+        assert_eq!(victim(strategy, &backups), None, "{strategy:?}");
+    }
+    // nothing is deleted if everything is protected
+    let backups = candidates(&[([1, 20, 4], "2024-01-01_00-00-00", true), ([1, 20, 4], "2024-01-01_01-00-00", true), ([1, 20, 4], "2024-01-01_02-00-00", true)]);
+    for strategy in [RetentionStrategy::VersionFirst, RetentionStrategy::TimeFirst, RetentionStrategy::Oldest, RetentionStrategy::Newest] {
+        assert_eq!(victim(strategy, &backups), None, "{strategy:?}");
+    }
+}
+
+#[test]
+fn tar_progress_percent() {
+    let size = ByteSize::b(100 * TAR_RECORD_SIZE);
+    assert_eq!(tar_progress(0, size), 0);
+    // the first checkpoint is printed as `tar: 1000`
+    assert_eq!(tar_progress(TAR_CHECKPOINT_RECORDS, ByteSize::b(10 * TAR_CHECKPOINT_RECORDS * TAR_RECORD_SIZE)), 10);
+    assert_eq!(tar_progress(50, size), 50);
+    assert_eq!(tar_progress(100, size), 100);
+    // the archive is larger than the backup because of headers and padding
+    assert_eq!(tar_progress(120, size), 100);
+    assert_eq!(tar_progress(1, ByteSize::default()), 100);
+}
+
+#[test]
+fn room_requires_both_thresholds() {
+    let total = ByteSize::gb(100);
+    // 10 GB must remain free either way
+    assert_eq!(required_space_on(total, ByteSize::gb(5), ByteSize::gb(10), 5.0), ByteSize::gb(15));
+    assert_eq!(required_space_on(total, ByteSize::gb(5), ByteSize::gb(1), 10.0), ByteSize::gb(15));
+    // enough for the byte threshold but not the percentage
+    assert!(!has_room_on(total, ByteSize::gb(14), ByteSize::gb(5), ByteSize::gb(1), 10.0));
+    // enough for the percentage but not the byte threshold
+    assert!(!has_room_on(total, ByteSize::gb(14), ByteSize::gb(5), ByteSize::gb(10), 1.0));
+    assert!(has_room_on(total, ByteSize::gb(15), ByteSize::gb(5), ByteSize::gb(10), 10.0));
+    // percentages are rounded up to the next byte
+    assert_eq!(required_space_on(ByteSize::b(999), ByteSize::default(), ByteSize::default(), 10.0), ByteSize::b(100));
+    assert!(has_room_on(total, ByteSize::gb(1), ByteSize::gb(1), ByteSize::default(), 0.0));
+}
+
+#[test]
+fn timestamp_formats_round_trip() -> Result<(), Error> {
+    let timestamp = Utc.with_ymd_and_hms(2024, 1, 31, 4, 5, 6).single().expect("valid date");
+    for (format, formatted) in [
+        (DEFAULT_TIMESTAMP_FORMAT, "2024-01-31_04-05-06"),
+        ("%Y%m%d%H%M%S", "20240131040506"),
+        ("%Y-%m-%dT%H.%M.%S", "2024-01-31T04.05.06"),
+        ("backup_%d-%m-%Y_%H-%M-%S", "backup_31-01-2024_04-05-06"),
+    ] {
+        let format = TimestampFormat::new(format)?;
+        assert_eq!(format.display(timestamp).to_string(), formatted);
+        assert_eq!(format.parse(formatted)?, timestamp);
+        // the version is separated by the first underscore after the timestamp, even if the format contains underscores
+        assert_eq!(format.parse_filename(&format!("{formatted}_1.20.5-pre1.tar.zst"))?, (timestamp, "1.20.5-pre1", Compression::Zstd));
+        assert!(format.parse_filename(formatted).is_err());
+    }
+    Ok(())
+}
+
+#[test]
+fn invalid_timestamp_formats() {
+    for format in [
+        "%Y-%m-%d",
+        "%Y-%m-%d_%H:%M:%S",
+        "%Y/%m/%d_%H-%M-%S",
+        "%Y-%m-%d_%H-%M-%S%",
+        "%Y-%m-%d_%H-%M-%S_%Y",
+        "%s",
+        "%Y-%m-%d_%H-%M-%S%.f",
+        "",
+    ] {
+        assert!(matches!(TimestampFormat::new(format), Err(Error::TimestampFormat(_))), "{format:?} should be rejected");
+    }
+}
+
+#[tokio::test]
+async fn make_room_keeps_protected_newest() -> Result<(), Error> {
+    let temp = tempfile::tempdir()?;
+    let world = mock_world(temp.path(), false).await?;
+    let mut options = options(temp.path().join("backup"));
+    options.min_free_percent = 100.0;
+    for (hour, version) in [(0, "1.20.4"), (1, "1.20.4"), (2, "24w14a"), (3, "1.20.5"), (4, "1.20.5")] {
+        synthetic_backup(&options, &world, &format!("2024-01-01_{hour:02}-00-00"), version, 1024).await?;
+    }
+    // like do_backup before making room for a new backup
+    options.protected.borrow_mut().insert(newest_backup(&options, &options.backup_path.join(world.to_string())).await?.expect("backups were created").timestamp);
+    assert!(!make_room(&options, ByteSize::kb(1), &world).await?);
+    assert_eq!(filenames(&options, &world).await?, ["2024-01-01_04-00-00_1.20.5"]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn make_room_keeps_last() -> Result<(), Error> {
+    for keep_last in [1, 2, 4] {
+        for strategy in [RetentionStrategy::VersionFirst, RetentionStrategy::TimeFirst, RetentionStrategy::Oldest, RetentionStrategy::Newest] {
+            let temp = tempfile::tempdir()?;
+            let world = mock_world(temp.path(), false).await?;
+            let mut options = options(temp.path().join("backup"));
+            options.retention_strategy = strategy;
+            options.keep_last = keep_last;
+            // never satisfied, so everything that may be deleted is
+            options.min_free_percent = 100.0;
+            let mut all = Vec::default();
+            for (hour, version) in [(0, "1.20.4"), (1, "1.20.4"), (2, "24w14a"), (3, "1.20.5"), (4, "1.20.5"), (5, "1.20.5")] {
+                synthetic_backup(&options, &world, &format!("2024-01-01_{hour:02}-00-00"), version, 1024).await?;
+                all.push(format!("2024-01-01_{hour:02}-00-00_{version}"));
+            }
+            assert!(!make_room(&options, ByteSize::kb(1), &world).await?);
+            assert_eq!(filenames(&options, &world).await?, all[all.len() - keep_last..], "keep_last = {keep_last} with {strategy:?}");
+        }
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn walk_while_files_disappear() -> Result<(), Error> {
+    let temp = tempfile::tempdir()?;
+    let root = temp.path().join("world");
+    synthetic_tree(&root, 2)?;
+    let stop = Arc::new(std::sync::atomic::AtomicBool::default());
+    // like a running server creating and deleting temporary files and directories
+    let churn = std::thread::spawn({
+        let root = root.clone();
+        let stop = stop.clone();
+        move || -> Result<(), Error> {
+            while !stop.load(std::sync::atomic::Ordering::Relaxed) {
+                for idx in 0..DIR_SIZE_CONCURRENCY * 2 {
+                    std::fs::write(root.join(format!("session{idx}.lock")), b"lock").at(&root)?;
+                }
+                synthetic_tree(&root.join("tmp"), 2)?;
+                for idx in 0..DIR_SIZE_CONCURRENCY * 2 {
+                    std::fs::remove_file(root.join(format!("session{idx}.lock"))).at(&root)?;
+                }
+                std::fs::remove_dir_all(root.join("tmp")).at(&root)?;
+            }
+            Ok(())
+        }
+    });
+    let res = async {
+        for _ in 0..200 {
+            dir_size(&root).await?;
+            latest_modification(&root, Arc::default(), PathBuf::default()).await?;
+            file_count(&root).await?;
+        }
+        Ok::<_, Error>(())
+    }.await;
+    stop.store(true, std::sync::atomic::Ordering::Relaxed);
+    churn.join().expect("churn thread panicked")?;
+    res?;
+    // the files which were there the whole time are still counted
+    assert!(file_count(&root).await? >= 3);
+    Ok(())
+}